@@ -1,9 +1,11 @@
-use chrono::{Datelike, NaiveDateTime, Timelike};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
 use itertools::Itertools;
 
-use std::collections::HashMap;
+use std::cmp::{max, Reverse};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{self, Display};
 use std::io;
+use std::ops::Range;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum GuardState {
@@ -12,12 +14,6 @@ enum GuardState {
     Begin,
 }
 
-impl Default for GuardState {
-    fn default() -> Self {
-        GuardState::Awake
-    }
-}
-
 impl Display for GuardState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -34,132 +30,306 @@ struct Event {
     state: GuardState,
 }
 
-fn parse_event(e: &str, guardhint: usize) -> Result<Event, super::StarError> {
-    if e.len() < 27 {
-        return Err(format!("Input too short \"{}\"", e).into());
-    }
-    let date =
-        NaiveDateTime::parse_from_str(&e[..18], "[%Y-%m-%d %H:%M]").map_err(|e| e.to_string())?;
-    let mut tokens = e[19..].split('#');
-
-    match tokens.next() {
-        Some("Guard ") => {
-            let guard_id = tokens
-                .next()
-                .ok_or_else(|| format!("Unexpected line ending \"{}\"", e))?
-                .split_whitespace()
-                .next()
-                .ok_or_else(|| format!("Expected another whitespace \"{}\"", e))?
-                .parse::<usize>()?;
-            Ok(Event {
-                timestamp: date,
-                guard_id,
-                state: GuardState::Begin,
-            })
-        }
-        Some("falls asleep") => Ok(Event {
+/// Parses a log line, attributing sleeping and waking up to `guardhint`.
+fn parse_event(e: &str, guardhint: usize) -> Result<Event, Anomaly> {
+    let close = e
+        .find(']')
+        .ok_or_else(|| Anomaly::BadTimestamp(e.to_string()))?;
+    let date = NaiveDateTime::parse_from_str(&e[..=close], "[%Y-%m-%d %H:%M]")
+        .map_err(|_| Anomaly::BadTimestamp(e.to_string()))?;
+    let text = e[close + 1..].trim();
+
+    if let Some(guard) = text.strip_prefix("Guard #") {
+        let guard_id = guard
+            .split_whitespace()
+            .next()
+            .and_then(|id| id.parse::<usize>().ok())
+            .ok_or_else(|| Anomaly::UnknownEvent(e.to_string()))?;
+        return Ok(Event {
+            timestamp: date,
+            guard_id,
+            state: GuardState::Begin,
+        });
+    }
+
+    match text {
+        "falls asleep" => Ok(Event {
             timestamp: date,
             guard_id: guardhint,
             state: GuardState::Asleep,
         }),
-        Some("wakes up") => Ok(Event {
+        "wakes up" => Ok(Event {
             timestamp: date,
             guard_id: guardhint,
             state: GuardState::Awake,
         }),
-        Some(&_) => Err("?".into()),
-        None => Err(format!("Unknown event \"{}\"", e).into()),
+        _ => Err(Anomaly::UnknownEvent(e.to_string())),
+    }
+}
+
+/// Inconsistencies found while reading a guard log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Anomaly {
+    BadTimestamp(String),
+    UnknownEvent(String),
+    SleepWithoutGuard,
+    NestedSleep,
+    WakeWithoutSleep,
+    ShiftEndsAsleep(usize),
+    DuplicateTimestamp(NaiveDateTime),
+    OffHourShift(usize),
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Anomaly::BadTimestamp(e) => write!(f, "bad timestamp \"{}\"", e),
+            Anomaly::UnknownEvent(e) => write!(f, "unknown event \"{}\"", e),
+            Anomaly::SleepWithoutGuard => write!(f, "falls asleep before any guard begins"),
+            Anomaly::NestedSleep => write!(f, "falls asleep while already asleep"),
+            Anomaly::WakeWithoutSleep => write!(f, "wakes up without being asleep"),
+            Anomaly::ShiftEndsAsleep(gid) => write!(f, "guard #{} never wakes up", gid),
+            Anomaly::DuplicateTimestamp(t) => write!(f, "more than one event at {}", t),
+            Anomaly::OffHourShift(gid) => write!(
+                f,
+                "guard #{} begins a shift outside the hour before midnight and the midnight hour",
+                gid
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct LogIssue {
+    line: usize,
+    anomaly: Anomaly,
+}
+
+impl Display for LogIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.anomaly)
     }
 }
 
-fn parse_events(logs: impl Iterator<Item = String>) -> Result<Vec<Event>, super::StarError> {
+/// How to deal with an inconsistent guard log. `Strict` refuses the log, `Lenient` drops the
+/// offending events and carries on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Mode {
+    Strict,
+    Lenient,
+}
+
+impl Mode {
+    fn from_options(opts: &super::Options) -> Mode {
+        if opts.flag("lenient") {
+            Mode::Lenient
+        } else {
+            Mode::Strict
+        }
+    }
+}
+
+/// Parses the chronologically sorted log, given as (line number, line) pairs. Returns the
+/// consistent events and an issue for every line that didn't fit in.
+fn parse_events(logs: &[(usize, String)]) -> (Vec<Event>, Vec<LogIssue>) {
     let mut events: Vec<Event> = Vec::new();
-    let mut lastid: usize = 0;
+    let mut issues = Vec::new();
+    let mut guard: Option<usize> = None;
+    let mut asleep: Option<usize> = None;
+    let mut last: Option<NaiveDateTime> = None;
+
+    for (line, l) in logs {
+        let mut report = |anomaly| {
+            issues.push(LogIssue {
+                line: *line,
+                anomaly,
+            })
+        };
+
+        let e = match parse_event(l, guard.unwrap_or(0)) {
+            Ok(e) => e,
+            Err(anomaly) => {
+                report(anomaly);
+                continue;
+            }
+        };
+        if last == Some(e.timestamp) {
+            report(Anomaly::DuplicateTimestamp(e.timestamp));
+        }
+        last = Some(e.timestamp);
 
-    for l in logs {
-        let e = parse_event(&l, lastid)?;
-        lastid = e.guard_id;
+        match e.state {
+            GuardState::Begin => {
+                let off_hour = !matches!(e.timestamp.hour(), 0 | 23);
+                if off_hour {
+                    report(Anomaly::OffHourShift(e.guard_id));
+                }
+                if let (Some(gid), Some(sleep_line)) = (guard, asleep) {
+                    issues.push(LogIssue {
+                        line: sleep_line,
+                        anomaly: Anomaly::ShiftEndsAsleep(gid),
+                    });
+                }
+                asleep = None;
+                // The events up to the next shift don't belong to anyone.
+                if off_hour {
+                    guard = None;
+                    continue;
+                }
+                guard = Some(e.guard_id);
+            }
+            GuardState::Asleep if guard.is_none() => {
+                report(Anomaly::SleepWithoutGuard);
+                continue;
+            }
+            GuardState::Asleep if asleep.is_some() => {
+                report(Anomaly::NestedSleep);
+                continue;
+            }
+            GuardState::Asleep => asleep = Some(*line),
+            GuardState::Awake if asleep.is_none() => {
+                report(Anomaly::WakeWithoutSleep);
+                continue;
+            }
+            GuardState::Awake => asleep = None,
+        }
         events.push(e);
     }
-    Ok(events)
+    if let (Some(gid), Some(sleep_line)) = (guard, asleep) {
+        issues.push(LogIssue {
+            line: sleep_line,
+            anomaly: Anomaly::ShiftEndsAsleep(gid),
+        });
+    }
+    issues.sort_by_key(|i| i.line);
+
+    (events, issues)
 }
 
-#[derive(Copy, Clone)]
+/// A guard's shift, attributed to the date of the midnight hour it covers.
+#[derive(Clone, Debug)]
 struct Shift {
     guard_id: usize,
-    month: u32,
-    day: u32,
-    minutes: [GuardState; 60],
+    date: NaiveDate,
+    naps: Vec<Range<NaiveDateTime>>,
 }
 
-impl Default for Shift {
-    fn default() -> Self {
-        Shift {
-            guard_id: 0,
-            month: 0,
-            day: 0,
-            minutes: [GuardState::Awake; 60],
-        }
-    }
-}
 impl Display for Shift {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
             "{:02}-{:02}  #{:4}  ",
-            self.month, self.day, self.guard_id
+            self.date.month(),
+            self.date.day(),
+            self.guard_id
         )?;
-        for m in self.minutes.iter() {
-            write!(f, "{}", m)?;
+        for m in 0..60 {
+            if self.asleep_at(m) {
+                write!(f, "{}", GuardState::Asleep)?;
+            } else {
+                write!(f, "{}", GuardState::Awake)?;
+            }
         }
         Ok(())
     }
 }
 
 impl Shift {
-    fn asleep_for(&self) -> usize {
-        self.minutes
-            .iter()
-            .filter(|s| **s == GuardState::Asleep)
-            .count()
-    }
-}
-
-impl<'a> From<&'a [Event]> for Shift {
-    fn from(events: &[Event]) -> Self {
-        if events.is_empty() {
-            panic!("Can't build shift from no events");
+    /// Builds a shift from its events, the first of which has to be the guard beginning the
+    /// shift. Shifts begin in the midnight hour or in the hour before, which belongs to the
+    /// following day. A guard still asleep at the end of the shift sleeps until the end of the
+    /// midnight hour.
+    fn build(events: &[Event]) -> Result<Shift, super::StarError> {
+        let (begin, rest) = events
+            .split_first()
+            .ok_or("Can't build shift from no events")?;
+        if begin.state != GuardState::Begin {
+            return Err(format!("Shift without a guard at {}", begin.timestamp).into());
         }
-        let mut s: Shift = Shift::default();
-        let last = events.last().unwrap();
-        s.guard_id = last.guard_id;
-        s.month = last.timestamp.month() as u32;
-        s.day = last.timestamp.day() as u32;
-        for e in events {
-            for m in s.minutes[e.timestamp.minute() as usize..].iter_mut() {
-                *m = e.state;
+
+        let date = match begin.timestamp.hour() {
+            0 => begin.timestamp.date(),
+            23 => begin.timestamp.date().succ(),
+            _ => {
+                return Err(format!(
+                    "Guard #{} begins a shift at {}, outside the hour before midnight and the \
+                     midnight hour",
+                    begin.guard_id, begin.timestamp
+                )
+                .into())
+            }
+        };
+        let midnight = date.and_hms(0, 0, 0);
+        let hour_end = midnight + Duration::hours(1);
+
+        let mut naps = Vec::new();
+        let mut asleep = None;
+        for e in rest {
+            match (e.state, asleep) {
+                (GuardState::Asleep, None) => asleep = Some(e.timestamp),
+                (GuardState::Awake, Some(start)) => {
+                    naps.push(start..e.timestamp);
+                    asleep = None;
+                }
+                _ => (),
             }
         }
-        s
-    }
-}
+        if let Some(start) = asleep {
+            naps.push(start..hour_end);
+        }
 
-fn group_shifts(events: &[Event]) -> Vec<Shift> {
-    let mut ret: Vec<Shift> = Vec::new();
-    for group in events.iter().map(|e| vec![*e]).coalesce(|mut xe, ye| {
-        if ye[0].state == GuardState::Begin {
-            Err((xe, ye))
-        } else {
-            xe.extend(ye);
-            Ok(xe)
+        if let Some(nap) = naps
+            .iter()
+            .find(|nap| nap.start < midnight || nap.end > hour_end)
+        {
+            return Err(format!(
+                "Guard #{} sleeps outside the midnight hour of {}: {} - {}",
+                begin.guard_id, date, nap.start, nap.end
+            )
+            .into());
         }
-    }) {
-        ret.push(Shift::from(&group[..]));
+
+        Ok(Shift {
+            guard_id: begin.guard_id,
+            date,
+            naps,
+        })
+    }
+
+    /// The naps as minute ranges of the midnight hour.
+    fn nap_minutes(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        let midnight = self.date.and_hms(0, 0, 0);
+        self.naps.iter().map(move |nap| {
+            (nap.start - midnight).num_minutes() as usize
+                ..(nap.end - midnight).num_minutes() as usize
+        })
+    }
+
+    fn asleep_at(&self, minute: usize) -> bool {
+        self.nap_minutes().any(|r| r.contains(&minute))
+    }
+
+    fn asleep_for(&self) -> usize {
+        self.nap_minutes().map(|r| r.len()).sum()
     }
-    ret
 }
 
-#[allow(dead_code)]
+fn group_shifts(events: &[Event]) -> Result<Vec<Shift>, super::StarError> {
+    events
+        .iter()
+        .map(|e| vec![*e])
+        .coalesce(|mut xe, ye| {
+            if ye[0].state == GuardState::Begin {
+                Err((xe, ye))
+            } else {
+                xe.extend(ye);
+                Ok(xe)
+            }
+        })
+        .map(|group| Shift::build(&group))
+        .collect()
+}
+
 fn print_events(shifts: &[Shift]) {
     println!("Date   ID     Minute");
     println!("              000000000011111111112222222222333333333344444444445555555555");
@@ -169,6 +339,7 @@ fn print_events(shifts: &[Shift]) {
     }
 }
 
+/// The guard asleep for the most minutes and how many, the lowest guard id in case of a tie.
 fn find_worst_guard(s: &[Shift]) -> (usize, usize) {
     let mut asleep = HashMap::new();
 
@@ -179,77 +350,681 @@ fn find_worst_guard(s: &[Shift]) -> (usize, usize) {
 
     let (gid, slept) = asleep
         .iter()
-        .max_by(|(_, xasleep), (_, yasleep)| xasleep.cmp(yasleep))
+        .max_by_key(|&(gid, slept)| (slept, Reverse(gid)))
         .expect("No maximum found");
     (*gid, *slept)
 }
 
+fn add_to_histogram(minutes: &mut [usize; 60], shift: &Shift) {
+    for nap in shift.nap_minutes() {
+        for m in minutes[nap].iter_mut() {
+            *m += 1
+        }
+    }
+}
+
 fn find_worst_minute(shifts: &[Shift], gid: usize) -> (usize, usize) {
     let mut minutes = [0usize; 60];
     for s in shifts.iter().filter(|e| e.guard_id == gid) {
-        for (i, m) in minutes.iter_mut().enumerate() {
-            if s.minutes[i] == GuardState::Asleep {
-                *m += 1
-            }
-        }
+        add_to_histogram(&mut minutes, s);
     }
+    worst_minute(&minutes)
+}
+
+/// The minute counted most often and its count, the earliest minute in case of a tie.
+fn worst_minute(minutes: &[usize; 60]) -> (usize, usize) {
     let (minute, count) = minutes
         .iter()
         .enumerate()
-        .max_by(|(_, xs), (_, ys)| xs.cmp(ys))
+        .max_by_key(|&(m, count)| (count, Reverse(m)))
         .expect("Can't find worst minute");
     (minute, *count)
 }
 
+/// Sleep statistics of a single guard over all of their shifts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct GuardStats {
+    guard_id: usize,
+    shifts: usize,
+    asleep: usize,
+    histogram: [usize; 60],
+    longest_nap: usize,
+}
+
+impl GuardStats {
+    fn new(guard_id: usize) -> GuardStats {
+        GuardStats {
+            guard_id,
+            shifts: 0,
+            asleep: 0,
+            histogram: [0; 60],
+            longest_nap: 0,
+        }
+    }
+
+    fn average(&self) -> f64 {
+        self.asleep as f64 / self.shifts as f64
+    }
+
+    /// The minutes the guard was asleep the most, all of them in case of a tie, and how often
+    /// that was. Guards that never sleep have no favourite minute.
+    fn favourite_minutes(&self) -> (Vec<usize>, usize) {
+        let (_, count) = worst_minute(&self.histogram);
+        if count == 0 {
+            return (Vec::new(), 0);
+        }
+        let minutes = (0..60).filter(|&m| self.histogram[m] == count).collect();
+        (minutes, count)
+    }
+}
+
+/// Statistics for every guard, ordered by guard id.
+fn guard_stats(shifts: &[Shift]) -> Vec<GuardStats> {
+    let mut stats = BTreeMap::new();
+    for s in shifts {
+        let stat = stats
+            .entry(s.guard_id)
+            .or_insert_with(|| GuardStats::new(s.guard_id));
+        stat.shifts += 1;
+        stat.asleep += s.asleep_for();
+        stat.longest_nap = s.nap_minutes().map(|r| r.len()).fold(stat.longest_nap, max);
+        add_to_histogram(&mut stat.histogram, s);
+    }
+    stats.into_values().collect()
+}
+
+/// Renders the sleep count of a minute as a single character: `.` for never, then `1`-`9`
+/// and `a`-`z`, and `+` for anything above 35.
+fn count_char(count: usize) -> char {
+    match count {
+        0 => '.',
+        1..=35 => std::char::from_digit(count as u32, 36).unwrap(),
+        _ => '+',
+    }
+}
+
+fn stats_chart(stats: &[GuardStats], sleepiest: Option<usize>) -> String {
+    let mut ret = String::new();
+    ret += "ID     Shifts  Asleep   Avg  Nap  Minute\n";
+    ret += "                                  000000000011111111112222222222333333333344444444445555555555\n";
+    ret += "                                  012345678901234567890123456789012345678901234567890123456789\n";
+    for s in stats {
+        let (minutes, count) = s.favourite_minutes();
+        ret += &format!(
+            "#{:4}  {:6}  {:6}  {:4.1}  {:3}  {}  {}x at {}{}\n",
+            s.guard_id,
+            s.shifts,
+            s.asleep,
+            s.average(),
+            s.longest_nap,
+            s.histogram
+                .iter()
+                .cloned()
+                .map(count_char)
+                .collect::<String>(),
+            count,
+            minutes.iter().join(", "),
+            if Some(s.guard_id) == sleepiest {
+                "  sleepiest"
+            } else {
+                ""
+            },
+        );
+    }
+    ret
+}
+
+fn stats_csv(stats: &[GuardStats]) -> String {
+    let mut ret =
+        String::from("guard,shifts,asleep,average,favourite_minutes,favourite_count,longest_nap");
+    for m in 0..60 {
+        ret += &format!(",m{:02}", m);
+    }
+    ret += "\n";
+    for s in stats {
+        let (minutes, count) = s.favourite_minutes();
+        ret += &format!(
+            "{},{},{},{:.2},{},{},{},{}\n",
+            s.guard_id,
+            s.shifts,
+            s.asleep,
+            s.average(),
+            minutes.iter().join(";"),
+            count,
+            s.longest_nap,
+            s.histogram.iter().join(",")
+        );
+    }
+    ret
+}
+
+/// Builds the shifts of a log. In strict mode any issue is an error, in lenient mode the issues
+/// are returned along with the shifts built from the remaining events.
 fn build_shifts(
     lines: impl Iterator<Item = io::Result<String>>,
+    mode: Mode,
+) -> Result<(Vec<Shift>, Vec<LogIssue>), super::StarError> {
+    let mut logs = (1..)
+        .zip(lines)
+        .map(|(i, l)| l.map(|l| (i, l)))
+        .collect::<Result<Vec<_>, _>>()?;
+    logs.sort_by(|(_, x), (_, y)| x.cmp(y));
+
+    let (events, issues) = parse_events(&logs);
+    if mode == Mode::Strict && !issues.is_empty() {
+        let report = issues.iter().map(|i| i.to_string()).join("\n");
+        return Err(format!("Inconsistent guard log:\n{}", report).into());
+    }
+    Ok((group_shifts(&events)?, issues))
+}
+
+/// The shifts of the log, with `--lenient` after listing the issues that were ignored.
+fn read_shifts(
+    lines: impl Iterator<Item = io::Result<String>>,
+    opts: &super::Options,
 ) -> Result<Vec<Shift>, super::StarError> {
-    let mut logs = lines.collect::<Result<Vec<_>, _>>()?;
-    logs.sort();
-    let events = parse_events(logs.into_iter())?;
-    Ok(group_shifts(&events))
+    let (shifts, issues) = build_shifts(lines, Mode::from_options(opts))?;
+    if !issues.is_empty() {
+        println!("Ignoring inconsistencies in guard log:");
+        for i in &issues {
+            println!("{}", i);
+        }
+    }
+    Ok(shifts)
 }
 
-pub fn star1(lines: impl Iterator<Item = io::Result<String>>) -> super::StarResult {
-    let shifts = build_shifts(lines)?;
+/// The guard and minute picked by a `Strategy`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Choice {
+    guard_id: usize,
+    minute: usize,
+}
 
-    //print_events(&shifts);
+impl Choice {
+    fn answer(&self) -> usize {
+        self.guard_id * self.minute
+    }
+}
 
-    let (gid, slept) = find_worst_guard(&shifts);
-    let (worst, _) = find_worst_minute(&shifts, gid);
-    println!(
-        "Guard #{} was asleep for {} minutes. Most of the time at minute {}. Answer {}",
-        gid,
-        slept,
-        worst,
-        gid * worst
-    );
+/// A way to pick the guard and the minute to sneak past them.
+trait Strategy {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    /// Picks a guard and minute, or nothing if no guard ever fell asleep.
+    fn choose(&self, shifts: &[Shift]) -> Option<Choice>;
+}
 
-    Ok(())
+/// Picks the favourite minute of the guard with the highest `key`, ignoring guards who never
+/// sleep. Ties go to the lowest guard id.
+fn choose_by_stats(shifts: &[Shift], key: impl Fn(&GuardStats) -> f64) -> Option<Choice> {
+    let stats = guard_stats(shifts);
+    let best = stats
+        .iter()
+        .filter(|s| s.asleep > 0)
+        .max_by(|x, y| key(x).total_cmp(&key(y)).then(y.guard_id.cmp(&x.guard_id)))?;
+    Some(Choice {
+        guard_id: best.guard_id,
+        minute: best.favourite_minutes().0[0],
+    })
 }
 
-pub fn star2(lines: impl Iterator<Item = std::io::Result<String>>) -> super::StarResult {
-    let mut shifts = build_shifts(lines)?;
-    //print_events(&shifts);
-    shifts.sort_by_key(|s| s.guard_id);
-    //print_events(&shifts);
+/// Part 1: the guard asleep for the most minutes and the minute they sleep the most.
+struct SleepiestGuard;
 
-    let mut stat = HashMap::new();
-    for (gid, ss) in shifts.iter().group_by(|s| s.guard_id).into_iter() {
-        let (minute, count) = find_worst_minute(&ss.cloned().collect::<Vec<Shift>>(), gid);
-        stat.insert(gid, (minute, count));
+impl Strategy for SleepiestGuard {
+    fn name(&self) -> &'static str {
+        "sleepiest"
     }
+    fn description(&self) -> &'static str {
+        "Most minutes asleep"
+    }
+    fn choose(&self, shifts: &[Shift]) -> Option<Choice> {
+        if shifts.iter().all(|s| s.naps.is_empty()) {
+            return None;
+        }
+        let (gid, _) = find_worst_guard(shifts);
+        let (minute, _) = find_worst_minute(shifts, gid);
+        Some(Choice {
+            guard_id: gid,
+            minute,
+        })
+    }
+}
 
-    let (gid, (minute, count)) = stat
-        .iter()
-        .max_by(|(_, (_, xc)), (_, (_, yc))| xc.cmp(yc))
-        .expect("Can't find favorite minute");
+/// Part 2: the guard most frequently asleep on the same minute.
+struct FavouriteMinute;
+
+impl Strategy for FavouriteMinute {
+    fn name(&self) -> &'static str {
+        "minute"
+    }
+    fn description(&self) -> &'static str {
+        "Most frequently asleep on the same minute"
+    }
+    fn choose(&self, shifts: &[Shift]) -> Option<Choice> {
+        let mut shifts = shifts.to_vec();
+        shifts.sort_by_key(|s| s.guard_id);
+
+        let mut stat = HashMap::new();
+        for (gid, ss) in shifts.iter().group_by(|s| s.guard_id).into_iter() {
+            let (minute, count) = find_worst_minute(&ss.cloned().collect::<Vec<Shift>>(), gid);
+            stat.insert(gid, (minute, count));
+        }
+
+        stat.into_iter()
+            .filter(|(_, (_, count))| *count > 0)
+            .max_by_key(|&(gid, (_, count))| (count, Reverse(gid)))
+            .map(|(guard_id, (minute, _))| Choice { guard_id, minute })
+    }
+}
+
+/// The guard who fell asleep during the most shifts.
+struct MostShiftsAsleep;
+
+impl Strategy for MostShiftsAsleep {
+    fn name(&self) -> &'static str {
+        "shifts"
+    }
+    fn description(&self) -> &'static str {
+        "Asleep during the most shifts"
+    }
+    fn choose(&self, shifts: &[Shift]) -> Option<Choice> {
+        let mut napping = HashMap::new();
+        for s in shifts.iter().filter(|s| !s.naps.is_empty()) {
+            *napping.entry(s.guard_id).or_insert(0) += 1;
+        }
+        choose_by_stats(shifts, |s| {
+            napping.get(&s.guard_id).cloned().unwrap_or(0) as f64
+        })
+    }
+}
+
+/// The guard asleep for the largest part of their shifts' midnight hours.
+struct SleepRatio;
+
+impl Strategy for SleepRatio {
+    fn name(&self) -> &'static str {
+        "ratio"
+    }
+    fn description(&self) -> &'static str {
+        "Highest ratio of the midnight hour asleep"
+    }
+    fn choose(&self, shifts: &[Shift]) -> Option<Choice> {
+        choose_by_stats(shifts, |s| s.average() / 60.0)
+    }
+}
+
+/// The guard whose asleep minutes vary the least around their mean, i.e. who keeps sleeping
+/// at the same time of the hour.
+struct ConsistentMinute;
+
+impl ConsistentMinute {
+    fn variance(s: &GuardStats) -> f64 {
+        let n = s.asleep as f64;
+        let mean = (0..60).map(|m| (m * s.histogram[m]) as f64).sum::<f64>() / n;
+        (0..60)
+            .map(|m| s.histogram[m] as f64 * (m as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n
+    }
+}
+
+impl Strategy for ConsistentMinute {
+    fn name(&self) -> &'static str {
+        "variance"
+    }
+    fn description(&self) -> &'static str {
+        "Least variance of the minutes asleep"
+    }
+    fn choose(&self, shifts: &[Shift]) -> Option<Choice> {
+        choose_by_stats(shifts, |s| -ConsistentMinute::variance(s))
+    }
+}
+
+fn strategies() -> Vec<Box<dyn Strategy>> {
+    vec![
+        Box::new(SleepiestGuard),
+        Box::new(FavouriteMinute),
+        Box::new(MostShiftsAsleep),
+        Box::new(SleepRatio),
+        Box::new(ConsistentMinute),
+    ]
+}
+
+fn print_choice(strategy: &dyn Strategy, shifts: &[Shift]) -> super::StarResult {
+    let choice = strategy
+        .choose(shifts)
+        .ok_or_else(|| format!("{}: no guard ever fell asleep", strategy.name()))?;
     println!(
-        "Guard #{} likes minute {} the most with {} occations. Answer {}",
-        gid,
-        minute,
-        count,
-        gid * minute
+        "{} ({}): guard #{} at minute {}. Answer {}",
+        strategy.description(),
+        strategy.name(),
+        choice.guard_id,
+        choice.minute,
+        choice.answer()
     );
     Ok(())
 }
+
+pub fn star1(
+    lines: impl Iterator<Item = io::Result<String>>,
+    opts: &super::Options,
+) -> super::StarResult {
+    let shifts = read_shifts(lines, opts)?;
+    print_choice(&SleepiestGuard, &shifts)
+}
+
+pub fn star2(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    opts: &super::Options,
+) -> super::StarResult {
+    let shifts = read_shifts(lines, opts)?;
+    print_choice(&FavouriteMinute, &shifts)
+}
+
+/// Runs the strategy named by `--strategy=<name>`, or all of them for `--strategy=all`.
+pub fn select(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    opts: &super::Options,
+) -> super::StarResult {
+    let shifts = read_shifts(lines, opts)?;
+    let name = match opts.get("strategy") {
+        Some("") | None => "all",
+        Some(name) => name,
+    };
+    let strategies = strategies()
+        .into_iter()
+        .filter(|s| name == "all" || name == s.name())
+        .collect::<Vec<_>>();
+    if strategies.is_empty() {
+        let names = self::strategies().iter().map(|s| s.name()).join(", ");
+        return Err(format!(
+            "Unknown strategy \"{}\", expected one of: all, {}",
+            name, names
+        )
+        .into());
+    }
+    for s in strategies {
+        print_choice(s.as_ref(), &shifts)?;
+    }
+    Ok(())
+}
+
+/// Prints `--report=shifts` (every shift), `--report=chart` (per guard statistics) or
+/// `--report=csv` (the statistics as CSV).
+pub fn report(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    opts: &super::Options,
+) -> super::StarResult {
+    let shifts = read_shifts(lines, opts)?;
+    let stats = guard_stats(&shifts);
+    match opts.get("report") {
+        Some("shifts") => print_events(&shifts),
+        Some("chart") | Some("") => {
+            let sleepiest = if shifts.is_empty() {
+                None
+            } else {
+                Some(find_worst_guard(&shifts).0)
+            };
+            print!("{}", stats_chart(&stats, sleepiest))
+        }
+        Some("csv") => print!("{}", stats_csv(&stats)),
+        Some(r) => return Err(format!("Unknown report \"{}\"", r).into()),
+        None => return Err("No report requested".into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader};
+
+    fn example() -> Vec<Shift> {
+        let file = File::open("src/day04/test").unwrap();
+        build_shifts(BufReader::new(file).lines(), Mode::Strict)
+            .unwrap()
+            .0
+    }
+
+    fn shifts_of(log: &[&str]) -> Result<Vec<Shift>, crate::StarError> {
+        build_shifts(log.iter().map(|l| Ok(l.to_string())), Mode::Strict).map(|(s, _)| s)
+    }
+
+    fn issues_of(log: &[&str]) -> Vec<LogIssue> {
+        let logs = (1..)
+            .zip(log.iter().map(|l| l.to_string()))
+            .collect::<Vec<_>>();
+        parse_events(&logs).1
+    }
+
+    #[test]
+    fn example_answers() {
+        let shifts = example();
+        assert_eq!(find_worst_guard(&shifts), (10, 50));
+        assert_eq!(find_worst_minute(&shifts, 10), (24, 2));
+        assert_eq!(find_worst_minute(&shifts, 99), (45, 3));
+    }
+
+    #[test]
+    fn shift_before_midnight() {
+        let shifts = example();
+        assert_eq!(shifts[1].guard_id, 99);
+        assert_eq!(shifts[1].date, NaiveDate::from_ymd(1518, 11, 2));
+        assert_eq!(shifts[1].nap_minutes().collect::<Vec<_>>(), vec![40..50]);
+    }
+
+    #[test]
+    fn bad_lines() {
+        let log = [
+            "[1518-11-01 00:00] Guard #10 begins shift",
+            "[1518-11-01 00:0x] falls asleep",
+            "1518-11-01 00:10 falls asleep",
+            "[1518-11-01 00:20] Guard #ten begins shift",
+            "[1518-11-01 00:30] falls asleep",
+            "[1518-11-01 00:40] wakes up",
+        ];
+        let anomalies = issues_of(&log)
+            .into_iter()
+            .map(|i| (i.line, i.anomaly))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            anomalies,
+            vec![
+                (2, Anomaly::BadTimestamp(log[1].to_string())),
+                (3, Anomaly::BadTimestamp(log[2].to_string())),
+                (4, Anomaly::UnknownEvent(log[3].to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn off_hour_shift() {
+        let log = [
+            "[1518-11-01 22:50] Guard #10 begins shift",
+            "[1518-11-02 00:05] falls asleep",
+            "[1518-11-02 00:10] wakes up",
+            "[1518-11-02 23:58] Guard #99 begins shift",
+            "[1518-11-03 00:05] falls asleep",
+            "[1518-11-03 00:10] wakes up",
+        ];
+        let anomalies = issues_of(&log)
+            .into_iter()
+            .map(|i| (i.line, i.anomaly))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            anomalies,
+            vec![
+                (1, Anomaly::OffHourShift(10)),
+                (2, Anomaly::SleepWithoutGuard),
+                (3, Anomaly::WakeWithoutSleep),
+            ]
+        );
+        assert!(shifts_of(&log).is_err());
+
+        let (shifts, issues) =
+            build_shifts(log.iter().map(|l| Ok(l.to_string())), Mode::Lenient).unwrap();
+        assert_eq!(issues.len(), 3);
+        assert_eq!(shifts.len(), 1);
+        assert_eq!(shifts[0].guard_id, 99);
+        assert_eq!(shifts[0].date, NaiveDate::from_ymd(1518, 11, 3));
+
+        let events = [parse_event(log[0], 0).unwrap()];
+        assert!(Shift::build(&events).is_err());
+    }
+
+    #[test]
+    fn sleep_outside_midnight_hour() {
+        assert!(shifts_of(&[
+            "[1518-11-01 23:50] Guard #10 begins shift",
+            "[1518-11-01 23:55] falls asleep",
+            "[1518-11-02 00:10] wakes up",
+        ])
+        .is_err());
+        assert!(shifts_of(&[
+            "[1518-11-02 00:00] Guard #10 begins shift",
+            "[1518-11-02 00:55] falls asleep",
+            "[1518-11-02 01:05] wakes up",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn log_anomalies() {
+        let log = [
+            "[1518-11-01 00:01] falls asleep",
+            "[1518-11-01 00:02] Guard #10 begins shift",
+            "[1518-11-01 00:05] falls asleep",
+            "[1518-11-01 00:06] falls asleep",
+            "[1518-11-01 00:25] wakes up",
+            "[1518-11-01 00:26] wakes up",
+            "[1518-11-01 00:30] dozes off",
+            "[1518-11-01 00:40] falls asleep",
+            "[1518-11-02 00:00] Guard #99 begins shift",
+            "[1518-11-02 00:00] falls asleep",
+        ];
+        let anomalies = issues_of(&log)
+            .into_iter()
+            .map(|i| (i.line, i.anomaly))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            anomalies,
+            vec![
+                (1, Anomaly::SleepWithoutGuard),
+                (4, Anomaly::NestedSleep),
+                (6, Anomaly::WakeWithoutSleep),
+                (7, Anomaly::UnknownEvent(log[6].to_string())),
+                (8, Anomaly::ShiftEndsAsleep(10)),
+                (
+                    10,
+                    Anomaly::DuplicateTimestamp(NaiveDate::from_ymd(1518, 11, 2).and_hms(0, 0, 0))
+                ),
+                (10, Anomaly::ShiftEndsAsleep(99)),
+            ]
+        );
+
+        assert!(shifts_of(&log).is_err());
+        let (shifts, issues) =
+            build_shifts(log.iter().map(|l| Ok(l.to_string())), Mode::Lenient).unwrap();
+        assert_eq!(issues, issues_of(&log));
+        assert_eq!(shifts.len(), 2);
+        assert_eq!(
+            shifts[0].nap_minutes().collect::<Vec<_>>(),
+            vec![5..25, 40..60]
+        );
+        assert_eq!(shifts[1].asleep_for(), 60);
+    }
+
+    #[test]
+    fn example_stats() {
+        let stats = guard_stats(&example());
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].guard_id, 10);
+        assert_eq!(stats[0].shifts, 2);
+        assert_eq!(stats[0].asleep, 50);
+        assert_eq!(stats[0].longest_nap, 25);
+        assert_eq!(stats[0].favourite_minutes(), (vec![24], 2));
+        assert_eq!(stats[1].average(), 10.0);
+        assert_eq!(stats[1].favourite_minutes(), (vec![45], 3));
+
+        let csv = stats_csv(&stats);
+        let rows = csv.lines().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[1].starts_with("10,2,50,25.00,24,2,25,0,0,0,0,0,1,"));
+
+        let mut tie = GuardStats::new(1);
+        tie.histogram[3] = 2;
+        tie.histogram[7] = 2;
+        assert_eq!(tie.favourite_minutes(), (vec![3, 7], 2));
+
+        let chart = stats_chart(&stats, Some(10));
+        assert!(chart.lines().nth(3).unwrap().ends_with("  sleepiest"));
+        assert!(!chart.lines().nth(4).unwrap().ends_with("  sleepiest"));
+    }
+
+    #[test]
+    fn empty_report() {
+        let mut opts = crate::Options::default();
+        opts.values
+            .insert("report".to_string(), "chart".to_string());
+        assert!(report(std::iter::empty(), &opts).is_ok());
+
+        opts.values.insert("lenient".to_string(), "".to_string());
+        let log = ["[1518-11-01 00:05] falls asleep"];
+        assert!(report(log.iter().map(|l| Ok(l.to_string())), &opts).is_ok());
+    }
+
+    #[test]
+    fn example_strategies() {
+        let shifts = example();
+        let choices = strategies()
+            .iter()
+            .map(|s| (s.name(), s.choose(&shifts).unwrap().answer()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            choices,
+            vec![
+                ("sleepiest", 240),
+                ("minute", 4455),
+                ("shifts", 4455),
+                ("ratio", 240),
+                ("variance", 4455),
+            ]
+        );
+        assert_eq!(SleepiestGuard.choose(&[]), None);
+    }
+
+    #[test]
+    fn ties() {
+        let mut log = Vec::new();
+        for (day, gid) in [(1, 7), (2, 3)] {
+            log.push(format!(
+                "[1518-11-{:02} 00:00] Guard #{} begins shift",
+                day, gid
+            ));
+            for (sleep, wake) in [(10, 20), (30, 40)] {
+                log.push(format!("[1518-11-{:02} 00:{}] falls asleep", day, sleep));
+                log.push(format!("[1518-11-{:02} 00:{}] wakes up", day, wake));
+            }
+        }
+        let log = log.iter().map(|l| l.as_str()).collect::<Vec<_>>();
+        let shifts = shifts_of(&log).unwrap();
+        // The HashMaps are seeded differently every time.
+        for _ in 0..20 {
+            assert_eq!(find_worst_guard(&shifts), (3, 20));
+            assert_eq!(find_worst_minute(&shifts, 7), (10, 1));
+            for s in strategies() {
+                assert_eq!(
+                    s.choose(&shifts),
+                    Some(Choice {
+                        guard_id: 3,
+                        minute: 10
+                    }),
+                    "{}",
+                    s.name()
+                );
+            }
+        }
+    }
+}
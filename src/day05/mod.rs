@@ -1,25 +1,112 @@
-use std::collections::BTreeSet;
+use itertools::Itertools;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::io;
-use std::iter::repeat;
 use std::iter::FromIterator;
 
 use super::*;
 
-fn can_react(a: char, b: char) -> bool {
-    a.to_ascii_lowercase() == b.to_ascii_lowercase() && a != b
+/// Decides which adjacent units react and which units are of the same type.
+trait Reaction {
+    /// Whether `a` immediately followed by `b` react.
+    fn reacts(&self, a: char, b: char) -> bool;
+    /// The type of a unit. Removing a type removes all of its units.
+    fn unit_type(&self, a: char) -> char;
+}
+
+/// The default rule set: the same letter in opposite case reacts.
+struct OppositeCase;
+
+impl Reaction for OppositeCase {
+    fn reacts(&self, a: char, b: char) -> bool {
+        a.eq_ignore_ascii_case(&b) && a != b
+    }
+    fn unit_type(&self, a: char) -> char {
+        a.to_ascii_lowercase()
+    }
+}
+
+/// Reactions between arbitrary characters, read from a rules file with one rule per line:
+/// `a ~ b` lets `ab` and `ba` react, `a > b` only lets `ab` react. The line `default` adds the
+/// rules of `OppositeCase`, empty lines and lines starting with `#` are ignored. Units
+/// connected by rules form a type, named after its smallest unit.
+#[derive(Debug, Default)]
+struct Rules {
+    pairs: HashSet<(char, char)>,
+    types: HashMap<char, char>,
+}
+
+impl Reaction for Rules {
+    fn reacts(&self, a: char, b: char) -> bool {
+        self.pairs.contains(&(a, b))
+    }
+    fn unit_type(&self, a: char) -> char {
+        self.types.get(&a).cloned().unwrap_or(a)
+    }
+}
+
+impl Rules {
+    fn parse(lines: impl Iterator<Item = io::Result<String>>) -> Result<Rules, StarError> {
+        let mut rules = Rules::default();
+        for (i, l) in (1..).zip(lines) {
+            let l = l?;
+            let l = l.trim();
+            if l.is_empty() || l.starts_with('#') {
+                continue;
+            }
+            if l == "default" {
+                for (a, b) in (b'a'..=b'z').zip(b'A'..=b'Z') {
+                    rules.add(a as char, b as char, true);
+                }
+                continue;
+            }
+
+            let tokens = l.split_whitespace().collect::<Vec<_>>();
+            let unit = |t: &str| {
+                let mut cs = t.chars();
+                match (cs.next(), cs.next()) {
+                    (Some(c), None) => Ok(c),
+                    _ => Err(format!("Line {}: expected a single unit, got \"{}\"", i, t)),
+                }
+            };
+            match tokens[..] {
+                [a, "~", b] => rules.add(unit(a)?, unit(b)?, true),
+                [a, ">", b] => rules.add(unit(a)?, unit(b)?, false),
+                _ => return Err(format!("Line {}: expected a rule, got \"{}\"", i, l).into()),
+            }
+        }
+        Ok(rules)
+    }
+
+    fn add(&mut self, a: char, b: char, symmetric: bool) {
+        self.pairs.insert((a, b));
+        if symmetric {
+            self.pairs.insert((b, a));
+        }
+
+        let (ta, tb) = (self.unit_type(a), self.unit_type(b));
+        let merged = ta.min(tb);
+        for t in self.types.values_mut() {
+            if *t == ta || *t == tb {
+                *t = merged;
+            }
+        }
+        self.types.insert(a, merged);
+        self.types.insert(b, merged);
+    }
 }
 
-fn react(v: &[char]) -> Vec<char> {
+fn react(v: &[char], rule: &impl Reaction) -> Vec<char> {
     let mut ret = Vec::new();
     if v.len() < 2 {
-        return ret;
+        return v.to_owned();
     }
 
     let mut last = v.first().cloned();
     for c in v[1..].iter() {
         match last {
             Some(l) => {
-                if !can_react(l, *c) {
+                if !rule.reacts(l, *c) {
                     ret.push(l);
                     last = Some(*c);
                 } else {
@@ -37,11 +124,11 @@ fn react(v: &[char]) -> Vec<char> {
     ret
 }
 
-fn react_full(v: &[char]) -> Vec<char> {
+fn react_full(v: &[char], rule: &impl Reaction) -> Vec<char> {
     let mut poly = v.to_owned();
     loop {
         let l = poly.len();
-        poly = react(&poly);
+        poly = react(&poly, rule);
         if l == poly.len() {
             break;
         }
@@ -49,44 +136,187 @@ fn react_full(v: &[char]) -> Vec<char> {
     poly
 }
 
-fn remove_and_react_full(v: &[char], a: char) -> Vec<char> {
+/// A reaction between the units at `left` and `right` of the original polymer. Units react in
+/// the pass of `react_full` given by `depth`: adjacent units in the first one, units that only
+/// met because the units between them reacted one pass after the last of those reactions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Reacted {
+    left: usize,
+    right: usize,
+    units: (char, char),
+    depth: usize,
+}
+
+/// Every reaction during a reduction, pass by pass, and the original positions of the
+/// surviving units.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Trace {
+    reactions: Vec<Reacted>,
+    survivors: Vec<usize>,
+}
+
+/// Same as `react_full`, but reacts the original positions of the units instead of the units.
+fn react_traced(v: &[char], rule: &impl Reaction) -> Trace {
+    let mut poly = (0..v.len()).collect::<Vec<_>>();
+    let mut reactions = Vec::new();
+    for depth in 1.. {
+        let mut ret = Vec::with_capacity(poly.len());
+        let mut last = None;
+        for &i in &poly {
+            match last {
+                Some(l) if rule.reacts(v[l], v[i]) => {
+                    reactions.push(Reacted {
+                        left: l,
+                        right: i,
+                        units: (v[l], v[i]),
+                        depth,
+                    });
+                    last = None;
+                }
+                Some(l) => {
+                    ret.push(l);
+                    last = Some(i);
+                }
+                None => last = Some(i),
+            }
+        }
+        ret.extend(last);
+        if ret.len() == poly.len() {
+            break;
+        }
+        poly = ret;
+    }
+    Trace {
+        reactions,
+        survivors: poly,
+    }
+}
+
+/// Shows the original polymer in lines of `width` units. Below every line the spans that
+/// reacted away are marked as `[---]`, and below that the depth of every reacted unit, `+`
+/// for depths above 9.
+fn visualise(v: &[char], trace: &Trace, width: usize) -> String {
+    let mut spans = vec![' '; v.len()];
+    let mut depths = vec![' '; v.len()];
+    // Reactions nest like parentheses, so going from left to right every reaction either lies
+    // within the last outermost one or right of it.
+    let mut outer_right = None;
+    for r in trace.reactions.iter().sorted_by_key(|r| r.left) {
+        if outer_right.is_none_or(|o| r.left > o) {
+            outer_right = Some(r.right);
+            for c in spans[r.left..=r.right].iter_mut() {
+                *c = '-';
+            }
+            spans[r.left] = '[';
+            spans[r.right] = ']';
+        }
+        let d = std::char::from_digit(r.depth as u32, 10).unwrap_or('+');
+        depths[r.left] = d;
+        depths[r.right] = d;
+    }
+
+    let mut ret = String::new();
+    for ((u, s), d) in v
+        .chunks(width)
+        .zip(spans.chunks(width))
+        .zip(depths.chunks(width))
+    {
+        ret += &String::from_iter(u);
+        ret += "\n";
+        ret += String::from_iter(s).trim_end();
+        ret += "\n";
+        ret += String::from_iter(d).trim_end();
+        ret += "\n";
+    }
+    ret
+}
+
+fn remove_and_react_full(v: &[char], types: &[char], rule: &impl Reaction) -> Vec<char> {
     let v = v
         .iter()
-        .filter(|c| c.to_ascii_lowercase() != a)
+        .filter(|&&c| !types.contains(&rule.unit_type(c)))
         .cloned()
         .collect::<Vec<_>>();
-    react_full(&v)
+    react_full(&v, rule)
 }
 
-pub fn star1(lines: impl Iterator<Item = io::Result<String>>) -> StarResult {
-    let poly = lines
+fn read_polymer(lines: impl Iterator<Item = io::Result<String>>) -> Result<Vec<char>, StarError> {
+    Ok(lines
         .take(1)
         .next()
         .ok_or("No input")??
         .chars()
-        .collect::<Vec<_>>();
-    let reacted = react_full(&poly);
+        .collect::<Vec<_>>())
+}
+
+fn solve1(poly: &[char], rule: &impl Reaction, trace: Option<&str>) {
+    let reacted = react_full(poly, rule);
     println!("Reduced polymer is {} long", reacted.len());
-    Ok(())
+
+    if let Some(detail) = trace {
+        let trace = react_traced(poly, rule);
+        println!(
+            "{} reactions, cascading up to depth {}, {} survivors",
+            trace.reactions.len(),
+            trace.reactions.iter().map(|r| r.depth).max().unwrap_or(0),
+            trace.survivors.len()
+        );
+        print!("{}", visualise(poly, &trace, 80));
+        if detail == "full" {
+            for r in &trace.reactions {
+                println!(
+                    "{:6} {:6}  {}{}  depth {}",
+                    r.left, r.right, r.units.0, r.units.1, r.depth
+                );
+            }
+            for &i in &trace.survivors {
+                println!("{:6}  {}", i, poly[i]);
+            }
+        }
+    }
 }
 
-pub fn star2(lines: impl Iterator<Item = std::io::Result<String>>) -> super::StarResult {
-    let poly = lines
-        .take(1)
-        .next()
-        .ok_or("No input")??
-        .chars()
+/// Runs `trial` on every item, split into one chunk per available thread. The results are
+/// in the order of the items.
+fn parallel_map<I: Sync, O: Send>(items: &[I], trial: impl Fn(&I) -> O + Sync) -> Vec<O> {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let chunk = items.len().div_ceil(threads).max(1);
+    let trial = &trial;
+    std::thread::scope(|scope| {
+        let handles = items
+            .chunks(chunk)
+            .map(|c| scope.spawn(move || c.iter().map(trial).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("Trial panicked"))
+            .collect()
+    })
+}
+
+/// Removes each unit type, and with `pairs` every pair of unit types, and prints the length
+/// of every resulting polymer.
+fn solve2(poly: &[char], rule: &(impl Reaction + Sync), pairs: bool) -> StarResult {
+    let alphabet = BTreeSet::from_iter(poly.iter().map(|&c| rule.unit_type(c)))
+        .into_iter()
         .collect::<Vec<_>>();
-    let alphabet = BTreeSet::from_iter(poly.iter().map(|c| c.to_ascii_lowercase()));
-    println!("Alphabet: {:?}", alphabet);
 
-    let (a, p) = alphabet
+    let lengths = parallel_map(&alphabet, |&a| {
+        remove_and_react_full(poly, &[a], rule).len()
+    });
+    println!("Removed  Length");
+    for (a, l) in alphabet.iter().zip(&lengths) {
+        println!("{:>7}  {:6}", a, l);
+    }
+
+    let (a, _) = alphabet
         .iter()
-        .zip(repeat(poly))
-        .map(|(a, p)| (a, remove_and_react_full(&p, *a)))
-        .min_by_key(|(_, p)| p.len())
+        .zip(&lengths)
+        .min_by_key(|(_, l)| *l)
         .ok_or("Unable to find best polymer")?;
-
+    let p = remove_and_react_full(poly, &[*a], rule);
     println!(
         "Best polymer found by removing '{}' with length {}: {}",
         a,
@@ -94,5 +324,155 @@ pub fn star2(lines: impl Iterator<Item = std::io::Result<String>>) -> super::Sta
         String::from_iter(p)
     );
 
+    if pairs && alphabet.len() < 2 {
+        println!("Only one unit type, no pairs to remove");
+    } else if pairs {
+        let combinations = alphabet
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &a)| alphabet[i + 1..].iter().map(move |&b| [a, b]))
+            .collect::<Vec<_>>();
+        let (pair, l) = combinations
+            .iter()
+            .zip(parallel_map(&combinations, |pair| {
+                remove_and_react_full(poly, pair, rule).len()
+            }))
+            .min_by_key(|(_, l)| *l)
+            .ok_or("Unable to find best pair")?;
+        println!(
+            "Best pair to remove is '{}' and '{}' with length {} ({} pairs tried)",
+            pair[0],
+            pair[1],
+            l,
+            combinations.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Uses the rules file given by `--rules=<file>` instead of the default rule set. With
+/// `--trace` the reactions are shown on the original polymer, `--trace=full` also lists every
+/// reaction and survivor with its original position.
+pub fn star1(lines: impl Iterator<Item = io::Result<String>>, opts: &Options) -> StarResult {
+    let poly = read_polymer(lines)?;
+    let trace = opts.get("trace");
+    match opts.get("rules") {
+        Some(rules) => solve1(&poly, &Rules::parse(solve(rules)?)?, trace),
+        None => solve1(&poly, &OppositeCase, trace),
+    }
     Ok(())
 }
+
+/// Also tries removing pairs of unit types with `--pairs`.
+pub fn star2(lines: impl Iterator<Item = std::io::Result<String>>, opts: &Options) -> StarResult {
+    let poly = read_polymer(lines)?;
+    let pairs = opts.flag("pairs");
+    match opts.get("rules") {
+        Some(rules) => solve2(&poly, &Rules::parse(solve(rules)?)?, pairs),
+        None => solve2(&poly, &OppositeCase, pairs),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn polymer(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    fn show(p: &[char]) -> String {
+        p.iter().collect()
+    }
+
+    fn rules(text: &str) -> Rules {
+        Rules::parse(text.lines().map(|l| Ok(l.to_string()))).unwrap()
+    }
+
+    #[test]
+    fn example() {
+        let poly = polymer("dabAcCaCBAcCcaDA");
+        assert_eq!(show(&react_full(&poly, &OppositeCase)), "dabCBAcaDA");
+        let lengths = "abcd"
+            .chars()
+            .map(|a| remove_and_react_full(&poly, &[a], &OppositeCase).len())
+            .collect::<Vec<_>>();
+        assert_eq!(lengths, vec![6, 8, 4, 6]);
+        assert_eq!(
+            parallel_map(&['a', 'b', 'c', 'd'], |&a| {
+                remove_and_react_full(&poly, &[a], &OppositeCase).len()
+            }),
+            lengths
+        );
+        assert_eq!(
+            show(&remove_and_react_full(&poly, &['a', 'c'], &OppositeCase)),
+            ""
+        );
+        assert_eq!(show(&react_full(&polymer("aAb"), &OppositeCase)), "b");
+        assert_eq!(show(&react_full(&polymer("aA"), &OppositeCase)), "");
+    }
+
+    #[test]
+    fn single_unit_type() {
+        for poly in ["aA", "a", "AAaa"] {
+            assert!(solve2(&polymer(poly), &OppositeCase, true).is_ok());
+        }
+        let poly = polymer("aA");
+        assert_eq!(remove_and_react_full(&poly, &['a'], &OppositeCase).len(), 0);
+    }
+
+    #[test]
+    fn rules_file() {
+        let default = rules("# the puzzle's rules\ndefault\n");
+        let poly = polymer("dabAcCaCBAcCcaDA");
+        assert_eq!(show(&react_full(&poly, &default)), "dabCBAcaDA");
+        assert_eq!(default.unit_type('C'), 'C');
+        assert_eq!(default.unit_type('c'), 'C');
+        assert_eq!(
+            show(&remove_and_react_full(&poly, &['C'], &default)),
+            "daDA"
+        );
+
+        let directional = rules("a > b");
+        assert_eq!(show(&react_full(&['a', 'b'], &directional)), "");
+        assert_eq!(show(&react_full(&['b', 'a'], &directional)), "ba");
+
+        let greek = rules("α ~ Ω\nΩ > β\n\n✓ ~ ✗");
+        let poly = polymer("αβΩαΩβ✓✗");
+        assert_eq!(show(&react_full(&poly, &greek)), "αβ");
+        assert_eq!(greek.unit_type('β'), 'Ω');
+        assert_eq!(greek.unit_type('✗'), '✓');
+        assert_eq!(greek.unit_type('x'), 'x');
+
+        assert!(Rules::parse(vec![Ok("ab ~ c".to_string())].into_iter()).is_err());
+        assert!(Rules::parse(vec![Ok("a = b".to_string())].into_iter()).is_err());
+    }
+
+    #[test]
+    fn trace() {
+        let poly = polymer("dabAcCaCBAcCcaDA");
+        let trace = react_traced(&poly, &OppositeCase);
+        let reactions = trace
+            .reactions
+            .iter()
+            .map(|r| (r.left, r.right, r.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(reactions, vec![(4, 5, 1), (10, 11, 1), (3, 6, 2)]);
+        assert_eq!(trace.reactions[2].units, ('A', 'a'));
+        assert_eq!(trace.survivors, vec![0, 1, 2, 7, 8, 9, 12, 13, 14, 15]);
+        assert_eq!(
+            visualise(&poly, &trace, 10),
+            "dabAcCaCBA\n   [--]\n   2112\ncCcaDA\n[]\n11\n"
+        );
+
+        let nested = polymer("abcCBAxX");
+        let trace = react_traced(&nested, &OppositeCase);
+        assert_eq!(trace.reactions.last().unwrap().depth, 3);
+        assert!(trace.survivors.is_empty());
+        assert_eq!(
+            visualise(&nested, &trace, 80),
+            "abcCBAxX\n[----][]\n32112311\n"
+        );
+    }
+}
@@ -1,25 +1,30 @@
-use std::collections::{HashMap, HashSet};
+use std::cmp::max;
+use std::collections::{BTreeSet, VecDeque};
 use std::io;
 use std::str::FromStr;
 
+use itertools::Itertools;
+
 use super::*;
 
 #[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
 struct Point {
     x: isize,
     y: isize,
+    z: isize,
 }
 
 impl Point {
-    fn new(x: isize, y: isize) -> Point {
-        Point { x, y }
+    fn new(x: isize, y: isize, z: isize) -> Point {
+        Point { x, y, z }
     }
 
-    fn dist(&self, other: &Point) -> isize {
-        (self.x - other.x).abs() + (self.y - other.y).abs()
+    fn coords(&self) -> [isize; 3] {
+        [self.x, self.y, self.z]
     }
 }
 
+/// Parses `x, y` or `x, y, z`. Points without `z` lie in the plane `z = 0`.
 impl FromStr for Point {
     type Err = StarError;
 
@@ -30,157 +35,1095 @@ impl FromStr for Point {
             .map(|s| s.parse::<isize>().map_err(StarError::from))
             .collect::<Result<Vec<_>, Self::Err>>()?;
 
-        if tok.len() != 2 {
-            Err(format!("Wrong number of coorinates \"{:?}\"", tok).into())
-        } else {
-            Ok(Point::new(tok[0], tok[1]))
+        match tok[..] {
+            [x, y] => Ok(Point::new(x, y, 0)),
+            [x, y, z] => Ok(Point::new(x, y, z)),
+            _ => Err(format!("Wrong number of coorinates \"{:?}\"", tok).into()),
         }
     }
 }
 
-fn bounding_box(points: &[Point], fatten: isize) -> (Point, Point) {
-    let minx = points
-        .iter()
-        .map(|p| p.x)
-        .min()
-        .expect("Error finding bounding box");
-    let miny = points
-        .iter()
-        .map(|p| p.y)
-        .min()
-        .expect("Error finding bounding box");
-    let maxx = points
-        .iter()
-        .map(|p| p.x)
-        .max()
-        .expect("Error finding bounding box");
-    let maxy = points
-        .iter()
-        .map(|p| p.y)
-        .max()
-        .expect("Error finding bounding box");
+/// Parses the points and their dimension, 2 or 3. All points need to have the same one.
+fn parse_points(
+    lines: impl Iterator<Item = io::Result<String>>,
+) -> Result<(Vec<Point>, usize), StarError> {
+    let mut points = Vec::new();
+    let mut dims = None;
+    for l in lines {
+        let l = l?;
+        let d = l.split(',').count();
+        if *dims.get_or_insert(d) != d {
+            return Err(format!("Expected {} coordinates, got \"{}\"", dims.unwrap(), l).into());
+        }
+        points.push(l.parse::<Point>()?);
+    }
+    if points.is_empty() {
+        return Err("No points".into());
+    }
+    Ok((points, dims.unwrap_or(2)))
+}
 
-    (
-        Point::new(minx - fatten, miny - fatten),
-        Point::new(maxx + fatten, maxy + fatten),
-    )
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum Metric {
+    #[default]
+    Manhattan,
+    Chebyshev,
+    SquaredEuclidean,
 }
 
-fn far_points(points: &[Point]) -> HashSet<Point> {
-    let (bbul, bblr) = bounding_box(points, 0);
-    let bbw = bblr.x - bbul.x + 1;
-    let bbh = bblr.y - bbul.y + 1;
-    let ul = Point::new(bbul.x - bbw, bbul.y - bbh);
-    let lr = Point::new(bblr.x + bbw, bblr.y + bbh);
+impl FromStr for Metric {
+    type Err = StarError;
 
-    let mut ret = HashSet::new();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "manhattan" => Ok(Metric::Manhattan),
+            "chebyshev" => Ok(Metric::Chebyshev),
+            "euclidean" => Ok(Metric::SquaredEuclidean),
+            _ => Err(format!("Unknown metric \"{}\"", s).into()),
+        }
+    }
+}
 
-    let insert = |x, y, map: &mut HashSet<Point>| {
-        if let Some(closest) = closest_to(points, Point::new(x, y)) {
-            map.insert(closest);
+impl Metric {
+    fn dist(self, a: &Point, b: &Point) -> isize {
+        let (a, b) = (a.coords(), b.coords());
+        let d = a.iter().zip(&b).map(|(a, b)| (a - b).abs());
+        match self {
+            Metric::Manhattan => d.sum(),
+            Metric::Chebyshev => d.max().unwrap_or(0),
+            Metric::SquaredEuclidean => d.map(|d| d * d).sum(),
         }
-    };
+    }
 
-    for x in ul.x..lr.x {
-        insert(x, ul.y, &mut ret);
-        insert(x, lr.y, &mut ret);
+    /// The steps to the locations exactly one further away from a point, as long as there is
+    /// such a step for every location. Squared Euclidean distances don't grow in unit steps.
+    fn steps(self, dims: usize) -> Option<Vec<[isize; 3]>> {
+        let zs = if dims == 3 { -1..=1 } else { 0..=0 };
+        let all = (-1..=1)
+            .flat_map(|x| (-1..=1).map(move |y| (x, y)))
+            .flat_map(|(x, y)| zs.clone().map(move |z| [x, y, z]))
+            .filter(|s| *s != [0, 0, 0]);
+        match self {
+            Metric::Manhattan => Some(
+                all.filter(|s: &[isize; 3]| s.iter().map(|c| c.abs()).sum::<isize>() == 1)
+                    .collect(),
+            ),
+            Metric::Chebyshev => Some(all.collect()),
+            Metric::SquaredEuclidean => None,
+        }
     }
-    for y in ul.y..lr.y {
-        insert(ul.x, y, &mut ret);
-        insert(lr.x, y, &mut ret);
+}
+
+fn bounding_box(points: &[Point], fatten: isize, dims: usize) -> (Point, Point) {
+    let min = |c: usize| {
+        points
+            .iter()
+            .map(|p| p.coords()[c])
+            .min()
+            .expect("Error finding bounding box")
+    };
+    let max = |c: usize| {
+        points
+            .iter()
+            .map(|p| p.coords()[c])
+            .max()
+            .expect("Error finding bounding box")
+    };
+    let fatten_z = if dims == 3 { fatten } else { 0 };
+
+    (
+        Point::new(min(0) - fatten, min(1) - fatten, min(2) - fatten_z),
+        Point::new(max(0) + fatten, max(1) + fatten, max(2) + fatten_z),
+    )
+}
+
+/// The closest point of a location.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Owner {
+    Unclaimed,
+    Point(usize),
+    Tie,
+}
+
+/// Whether no other point is at least as far out as point `i` along every axis, for one
+/// choice of direction along each axis. Far out in such a direction Chebyshev distances only
+/// depend on how far out the points are, so `i` stays closest to infinitely many locations.
+/// Otherwise the dominating point is at most as far from every location that is at least the
+/// points' largest spread along an axis away from `i`.
+fn undominated(points: &[Point], i: usize, dims: usize) -> bool {
+    let p = points[i].coords();
+    (0..1 << dims).any(|signs: usize| {
+        let out = |q: &Point, a: usize| {
+            let d = q.coords()[a] - p[a];
+            if (signs >> a) & 1 == 1 {
+                -d
+            } else {
+                d
+            }
+        };
+        points
+            .iter()
+            .enumerate()
+            .all(|(j, q)| j == i || (0..dims).any(|a| out(q, a) < 0))
+    })
+}
+
+/// Whether point `i` lies on the boundary of the convex hull of the points, i.e. all other
+/// points lie in a closed half-space through it. Far out along the outward normal `i` stays
+/// the closest point. A point strictly inside is beaten by a hull point in every direction.
+/// If such a half-space exists, there is one whose border goes through `dims - 1`
+/// independent directions to other points, unless they are all on a line.
+fn on_hull(points: &[Point], i: usize, dims: usize) -> bool {
+    let p = points[i].coords();
+    let vs = points
+        .iter()
+        .map(|q| {
+            let q = q.coords();
+            [q[0] - p[0], q[1] - p[1], q[2] - p[2]]
+        })
+        .collect::<Vec<_>>();
+    if vs
+        .iter()
+        .enumerate()
+        .any(|(j, v)| j != i && *v == [0, 0, 0])
+    {
+        return false;
     }
 
-    ret
+    let normals = if dims == 3 {
+        vs.iter()
+            .tuple_combinations()
+            .map(|(v, w)| {
+                [
+                    v[1] * w[2] - v[2] * w[1],
+                    v[2] * w[0] - v[0] * w[2],
+                    v[0] * w[1] - v[1] * w[0],
+                ]
+            })
+            .collect::<Vec<_>>()
+    } else {
+        vs.iter().map(|v| [-v[1], v[0], 0]).collect()
+    };
+    let normals = normals
+        .into_iter()
+        .filter(|n| *n != [0, 0, 0])
+        .collect::<Vec<_>>();
+    let dot = |n: &[isize; 3], v: &[isize; 3]| n.iter().zip(v).map(|(a, b)| a * b).sum::<isize>();
+    normals.is_empty()
+        || normals
+            .iter()
+            .any(|n| vs.iter().all(|v| dot(n, v) <= 0) || vs.iter().all(|v| dot(n, v) >= 0))
 }
 
-fn closest_to(points: &[Point], p: Point) -> Option<Point> {
-    let c = points
+fn det3(m: &[[f64; 3]; 3]) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+/// The corners of point `i`'s region for squared Euclidean distances: the centers of the
+/// circles (or spheres) through `i` and `dims` other points with no point strictly inside.
+fn region_corners(points: &[Point], i: usize, dims: usize) -> Vec<[f64; 3]> {
+    let p = points[i].coords().map(|c| c as f64);
+    let dist =
+        |c: &[f64; 3], q: &[f64; 3]| -> f64 { c.iter().zip(q).map(|(a, b)| (a - b).powi(2)).sum() };
+    let others = points
         .iter()
-        .min_by_key(|ps| ps.dist(&p))
-        .expect("Error finding closest Point");
-    for ps in points {
-        if ps == c {
+        .map(|q| q.coords().map(|c| c as f64))
+        .collect::<Vec<_>>();
+
+    let mut corners = Vec::new();
+    for qs in (0..points.len()).filter(|&j| j != i).combinations(dims) {
+        // Equally far from `i` and each of `qs`, and in the plane of the points in 2D.
+        let mut m = [[0.0; 3]; 3];
+        let mut b = [0.0; 3];
+        for a in 0..3 {
+            match qs.get(a) {
+                Some(&j) => {
+                    let q = others[j];
+                    for c in 0..3 {
+                        m[a][c] = 2.0 * (q[c] - p[c]);
+                    }
+                    b[a] = dist(&q, &[0.0; 3]) - dist(&p, &[0.0; 3]);
+                }
+                None => {
+                    m[a][a] = 1.0;
+                    b[a] = p[a];
+                }
+            }
+        }
+        let det = det3(&m);
+        if det.abs() < 1e-9 {
             continue;
         }
-        if ps.dist(&p) == c.dist(&p) {
-            return None;
+        let mut center = [0.0; 3];
+        for (c, x) in center.iter_mut().enumerate() {
+            let mut mc = m;
+            for a in 0..3 {
+                mc[a][c] = b[a];
+            }
+            *x = det3(&mc) / det;
+        }
+        let r = dist(&center, &p);
+        if others
+            .iter()
+            .all(|q| dist(&center, q) >= r - 1e-6 * r.max(1.0))
+        {
+            corners.push(center);
         }
     }
-    Some(*c)
+    corners
 }
 
-fn within_box(bb: (Point, Point)) -> impl Iterator<Item = (isize, isize)> {
-    let (ul, lr) = bb;
-    let w = (lr.x - ul.x + 1) as usize;
+/// A box holding every location of every finite region. Chebyshev regions reach at most the
+/// points' largest spread along an axis beyond the bounding box, see `undominated`. Finite
+/// squared Euclidean regions are convex, so the box around their corners holds them.
+fn finite_box(points: &[Point], dims: usize, metric: Metric) -> (Point, Point) {
+    match metric {
+        Metric::Manhattan => bounding_box(points, 0, dims),
+        Metric::Chebyshev => {
+            let (ul, lr) = bounding_box(points, 0, dims);
+            let (ul, lr) = (ul.coords(), lr.coords());
+            let spread = (0..dims).map(|a| lr[a] - ul[a]).max().unwrap_or(0);
+            bounding_box(points, spread, dims)
+        }
+        Metric::SquaredEuclidean => {
+            let (ul, lr) = bounding_box(points, 0, dims);
+            let (mut lo, mut hi) = (ul.coords(), lr.coords());
+            for i in (0..points.len()).filter(|&i| !on_hull(points, i, dims)) {
+                for c in region_corners(points, i, dims) {
+                    for a in 0..dims {
+                        lo[a] = lo[a].min(c[a].floor() as isize);
+                        hi[a] = hi[a].max(c[a].ceil() as isize);
+                    }
+                }
+            }
+            (
+                Point::new(lo[0], lo[1], lo[2]),
+                Point::new(hi[0], hi[1], hi[2]),
+            )
+        }
+    }
+}
 
-    (ul.x..=lr.x)
-        .cycle()
-        .zip((ul.y..=lr.y).flat_map(move |y| std::iter::repeat(y).take(w)))
+/// Every location that can belong to a finite region, labelled with its closest point.
+struct Regions {
+    ul: Point,
+    size: [usize; 3],
+    dims: usize,
+    metric: Metric,
+    owners: Vec<Owner>,
 }
 
-fn coverage(points: &[Point]) -> HashMap<Point, usize> {
-    let mut areas = HashMap::new();
-    for (x, y) in within_box(bounding_box(points, 0)) {
-        let p = Point::new(x, y);
-        if let Some(closest) = closest_to(points, p) {
-            //println!("{:?} is closest to {:?}", p, closest);
-            let e = areas.entry(closest).or_insert(0);
-            *e += 1;
-        } else {
-            //println!("{:?} has multiple closest points", p);
+impl Regions {
+    /// Labels the box given by `finite_box`. Where the metric allows it, with a breadth first
+    /// search starting from all points at once: a location's closest points are exactly those
+    /// of its neighbours one step closer to any point, so ties spread just like regions do.
+    /// Otherwise by comparing every location with every point.
+    fn flood(points: &[Point], dims: usize, metric: Metric) -> Regions {
+        let (ul, lr) = finite_box(points, dims, metric);
+        let size = [
+            (lr.x - ul.x + 1) as usize,
+            (lr.y - ul.y + 1) as usize,
+            (lr.z - ul.z + 1) as usize,
+        ];
+        let mut regions = Regions {
+            ul,
+            size,
+            dims,
+            metric,
+            owners: vec![Owner::Unclaimed; size.iter().product()],
+        };
+
+        match metric.steps(dims) {
+            Some(steps) => regions.fill(points, &steps),
+            None => {
+                for c in 0..regions.owners.len() {
+                    regions.owners[c] = closest_to(points, &regions.location(c), metric);
+                }
+            }
         }
+        regions
     }
-    areas
-}
 
-pub fn star1(lines: impl Iterator<Item = io::Result<String>>) -> StarResult {
-    let points = lines
-        .map(|l| l.map_err(StarError::from).and_then(|s| s.parse::<Point>()))
-        .collect::<Result<Vec<Point>, StarError>>()?;
+    fn fill(&mut self, points: &[Point], steps: &[[isize; 3]]) {
+        let mut dist = vec![usize::MAX; self.owners.len()];
+        let mut queue = VecDeque::new();
+
+        for (i, p) in points.iter().enumerate() {
+            let c = self.index(p).expect("Point outside of its bounding box");
+            if self.owners[c] == Owner::Unclaimed {
+                self.owners[c] = Owner::Point(i);
+                dist[c] = 0;
+                queue.push_back(c);
+            } else {
+                self.owners[c] = Owner::Tie;
+            }
+        }
 
-    for p in &points {
-        println!("{:?}", p);
+        while let Some(c) = queue.pop_front() {
+            let l = self.location(c);
+            for s in steps {
+                let n = match self.index(&Point::new(l.x + s[0], l.y + s[1], l.z + s[2])) {
+                    Some(n) => n,
+                    None => continue,
+                };
+                if dist[n] == usize::MAX {
+                    dist[n] = dist[c] + 1;
+                    self.owners[n] = self.owners[c];
+                    queue.push_back(n);
+                } else if dist[n] == dist[c] + 1 && self.owners[n] != self.owners[c] {
+                    self.owners[n] = Owner::Tie;
+                }
+            }
+        }
     }
-    println!("Bounding box {:?}", bounding_box(&points, 0));
 
-    let mut cover = coverage(&points);
-    for (p, a) in &cover {
-        println!("{:?}, {}", p, a);
+    fn index(&self, p: &Point) -> Option<usize> {
+        let mut idx = 0;
+        for ((&c, &o), &s) in p
+            .coords()
+            .iter()
+            .zip(&self.ul.coords())
+            .zip(&self.size)
+            .rev()
+        {
+            let c = c - o;
+            if c < 0 || c as usize >= s {
+                return None;
+            }
+            idx = idx * s + c as usize;
+        }
+        Some(idx)
     }
 
-    let far = far_points(&points);
-    println!("{:?}", far);
+    fn location(&self, idx: usize) -> Point {
+        let [w, h, _] = self.size;
+        Point::new(
+            self.ul.x + (idx % w) as isize,
+            self.ul.y + (idx / w % h) as isize,
+            self.ul.z + (idx / w / h) as isize,
+        )
+    }
 
-    for f in far {
-        cover.remove(&f);
+    /// The points with an infinite region. Manhattan regions only grow beyond the bounding
+    /// box where they touch its border, as every step away from the box adds one to the
+    /// distance to every point. With the other metrics regions touching the border can still
+    /// be finite.
+    fn infinite(&self, points: &[Point]) -> BTreeSet<usize> {
+        let unbounded = match self.metric {
+            Metric::Manhattan => return self.on_border(),
+            Metric::Chebyshev => undominated,
+            Metric::SquaredEuclidean => on_hull,
+        };
+        (0..points.len())
+            .filter(|&i| unbounded(points, i, self.dims))
+            .collect()
     }
 
-    let (p, a) = cover
-        .iter()
+    /// The points owning a location on the border of the box.
+    fn on_border(&self) -> BTreeSet<usize> {
+        let (ul, size) = (self.ul.coords(), self.size);
+        (0..self.owners.len())
+            .filter(|&c| {
+                let l = self.location(c).coords();
+                (0..self.dims).any(|d| l[d] == ul[d] || l[d] == ul[d] + size[d] as isize - 1)
+            })
+            .filter_map(|c| match self.owners[c] {
+                Owner::Point(i) => Some(i),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The number of locations closest to each point.
+    fn areas(&self, n_points: usize) -> Vec<usize> {
+        let mut areas = vec![0; n_points];
+        for o in &self.owners {
+            if let Owner::Point(i) = o {
+                areas[*i] += 1;
+            }
+        }
+        areas
+    }
+
+    /// The number of locations closest to more than one point.
+    fn ties(&self) -> usize {
+        self.owners.iter().filter(|&&o| o == Owner::Tie).count()
+    }
+}
+
+fn closest_to(points: &[Point], p: &Point, metric: Metric) -> Owner {
+    let mut ret = Owner::Unclaimed;
+    let mut best = isize::MAX;
+    for (i, ps) in points.iter().enumerate() {
+        let d = metric.dist(ps, p);
+        if d < best {
+            best = d;
+            ret = Owner::Point(i);
+        } else if d == best {
+            ret = Owner::Tie;
+        }
+    }
+    ret
+}
+
+impl Display for Regions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [w, h, _] = self.size;
+        for (z, slice) in self.owners.chunks(w * h).enumerate() {
+            if self.dims == 3 {
+                writeln!(f, "{},{},{}:", self.ul.x, self.ul.y, self.ul.z + z as isize)?;
+            } else {
+                writeln!(f, "{},{}:", self.ul.x, self.ul.y)?;
+            }
+            self.fmt_slice(f, slice)?;
+        }
+        Ok(())
+    }
+}
+
+impl Regions {
+    fn fmt_slice(&self, f: &mut fmt::Formatter, slice: &[Owner]) -> fmt::Result {
+        const NAMES: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        for row in slice.chunks(self.size[0]) {
+            for o in row {
+                let c = match o {
+                    Owner::Point(i) => NAMES.get(*i).map(|&c| c as char).unwrap_or('?'),
+                    _ => '.',
+                };
+                write!(f, "{}", c)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn within_box(bb: (Point, Point)) -> impl Iterator<Item = Point> {
+    let (ul, lr) = bb;
+    (ul.z..=lr.z).flat_map(move |z| {
+        (ul.y..=lr.y).flat_map(move |y| (ul.x..=lr.x).map(move |x| Point::new(x, y, z)))
+    })
+}
+
+fn largest_finite_area(points: &[Point], dims: usize, metric: Metric) -> Option<(usize, usize)> {
+    let regions = Regions::flood(points, dims, metric);
+    let infinite = regions.infinite(points);
+    regions
+        .areas(points.len())
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !infinite.contains(i))
         .max_by_key(|(_, a)| *a)
-        .expect("Expected a Point with maximum coverage");
-    println!("Point with maximum area of {}: {:?}", a, p);
+}
+
+/// Compares distances with the metric given by `--metric=manhattan|chebyshev|euclidean`.
+pub fn star1(lines: impl Iterator<Item = io::Result<String>>, opts: &Options) -> StarResult {
+    let (points, dims) = parse_points(lines)?;
+    let metric = opts.value("metric", Metric::default())?;
+
+    println!("Bounding box {:?}", bounding_box(&points, 0, dims));
+    let regions = Regions::flood(&points, dims, metric);
+    println!(
+        "{} infinite regions, {} locations tied",
+        regions.infinite(&points).len(),
+        regions.ties()
+    );
+
+    let (i, a) = largest_finite_area(&points, dims, metric)
+        .ok_or("Expected a Point with maximum coverage")?;
+    println!("Point with maximum area of {}: {:?}", a, points[i]);
 
     Ok(())
 }
 
-pub fn star2(lines: impl Iterator<Item = std::io::Result<String>>) -> super::StarResult {
-    let points = lines
-        .map(|l| l.map_err(StarError::from).and_then(|s| s.parse::<Point>()))
-        .collect::<Result<Vec<Point>, StarError>>()?;
+/// The total distance along a single axis from `v` to the coordinates `cs`, or nothing if it
+/// doesn't fit an `isize`. Only meaningful for metrics that add up over the axes.
+fn axis_total(cs: &[isize], v: isize, metric: Metric) -> Option<isize> {
+    cs.iter().try_fold(0isize, |total, c| {
+        let d = v.checked_sub(*c)?;
+        let d = match metric {
+            Metric::SquaredEuclidean => d.checked_mul(d)?,
+            _ => d.checked_abs()?,
+        };
+        total.checked_add(d)
+    })
+}
+
+/// `axis_total` for every `v` in `lo..=hi`, in O(range + points). Squared distances sum up
+/// to `n v² - 2 v Σc + Σc²`. Manhattan distances are swept from left to right over the sorted
+/// coordinates, with the sum of those left of `v`.
+fn axis_totals(
+    cs: &[isize],
+    lo: isize,
+    hi: isize,
+    metric: Metric,
+) -> Result<Vec<isize>, StarError> {
+    let too_large = || StarError::from("Total distance too large");
+    let n = cs.len() as isize;
+    if metric == Metric::SquaredEuclidean {
+        let sum = cs
+            .iter()
+            .try_fold(0isize, |s, &c| s.checked_add(c))
+            .ok_or_else(too_large)?;
+        let sum_sq = cs
+            .iter()
+            .try_fold(0isize, |s, &c| s.checked_add(c.checked_mul(c)?))
+            .ok_or_else(too_large)?;
+        let total = |v: isize| {
+            n.checked_mul(v)?
+                .checked_mul(v)?
+                .checked_sub(v.checked_mul(sum)?.checked_mul(2)?)?
+                .checked_add(sum_sq)
+        };
+        return (lo..=hi).map(|v| total(v).ok_or_else(too_large)).collect();
+    }
+
+    let mut sorted = cs.to_vec();
+    sorted.sort_unstable();
+    let total: isize = sorted.iter().sum();
+    let (mut left, mut left_sum) = (0, 0);
+    Ok((lo..=hi)
+        .map(|v| {
+            while left < sorted.len() && sorted[left] <= v {
+                left_sum += sorted[left];
+                left += 1;
+            }
+            let k = left as isize;
+            (v * k - left_sum) + (total - left_sum - v * (n - k))
+        })
+        .collect())
+}
+
+/// The locations with a total distance to all points below a threshold.
+struct SafeRegion {
+    ul: Point,
+    size: [usize; 3],
+    dims: usize,
+    cells: Vec<bool>,
+}
+
+impl SafeRegion {
+    /// Marks the safe locations from per axis totals, which metrics that add up over the axes
+    /// allow. So do 2D Chebyshev distances, which are half the Manhattan distances along the
+    /// diagonals `x + y` and `x - y`. There is no such trick in 3D, where the Chebyshev unit
+    /// ball is a cube with 8 corners, and no linear map turns it into the Manhattan one with 6,
+    /// so every location is checked against every point.
+    fn find(
+        points: &[Point],
+        dims: usize,
+        metric: Metric,
+        threshold: isize,
+    ) -> Result<SafeRegion, StarError> {
+        let bb = match metric {
+            Metric::Chebyshev => Some(bounding_box(
+                points,
+                max(threshold - 1, 0) / points.len() as isize,
+                dims,
+            )),
+            _ => SafeRegion::extent(points, dims, metric, threshold)?,
+        };
+        let (ul, lr) = match bb {
+            Some(bb) => bb,
+            None => {
+                return Ok(SafeRegion {
+                    ul: Point::default(),
+                    size: [0, 0, 0],
+                    dims,
+                    cells: Vec::new(),
+                })
+            }
+        };
+
+        let (lo, hi) = (ul.coords(), lr.coords());
+        let cells = match metric {
+            // Every location outside of the bounding box fattened by `pad` is more than `pad`
+            // away from every point.
+            Metric::Chebyshev if dims == 3 => within_box((ul, lr))
+                .map(|p| points.iter().map(|ps| metric.dist(ps, &p)).sum::<isize>() < threshold)
+                .collect(),
+            Metric::Chebyshev => {
+                let us = points.iter().map(|p| p.x + p.y).collect::<Vec<_>>();
+                let vs = points.iter().map(|p| p.x - p.y).collect::<Vec<_>>();
+                let (u_lo, v_lo) = (lo[0] + lo[1], lo[0] - hi[1]);
+                let u_totals = axis_totals(&us, u_lo, hi[0] + hi[1], Metric::Manhattan)?;
+                let v_totals = axis_totals(&vs, v_lo, hi[0] - lo[1], Metric::Manhattan)?;
+                let mut cells = Vec::new();
+                for y in lo[1]..=hi[1] {
+                    cells.extend((lo[0]..=hi[0]).map(|x| {
+                        let u = u_totals[(x + y - u_lo) as usize];
+                        let v = v_totals[(x - y - v_lo) as usize];
+                        u + v < 2 * threshold
+                    }));
+                }
+                cells
+            }
+            _ => {
+                let totals = (0..3)
+                    .map(|a| {
+                        let cs = points.iter().map(|p| p.coords()[a]).collect::<Vec<_>>();
+                        axis_totals(&cs, lo[a], hi[a], metric)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut cells = Vec::new();
+                for z in &totals[2] {
+                    for y in &totals[1] {
+                        cells.extend(totals[0].iter().map(|x| x + y + z < threshold));
+                    }
+                }
+                cells
+            }
+        };
 
-    let mut area = 0;
-    for (x, y) in within_box(bounding_box(&points, 10000 / points.len() as isize)) {
-        let p = Point::new(x, y);
-        let dist: isize = points.iter().map(|ps| ps.dist(&p)).sum();
-        if dist < 10000 {
-            area += 1;
+        Ok(SafeRegion {
+            ul,
+            size: [
+                (hi[0] - lo[0] + 1) as usize,
+                (hi[1] - lo[1] + 1) as usize,
+                (hi[2] - lo[2] + 1) as usize,
+            ],
+            dims,
+            cells,
+        })
+    }
+
+    /// The smallest box containing the safe region, for metrics that add up over the axes, or
+    /// nothing if there are no safe locations. A location can only be safe if its own axis'
+    /// total plus the smallest totals of the other axes is below the threshold. Each axis'
+    /// total is convex, so that holds on an interval around its minimum, which lies within
+    /// the bounding box of the points. And at both ends of the interval the other axes can
+    /// take their minimum, so no smaller box will do.
+    fn extent(
+        points: &[Point],
+        dims: usize,
+        metric: Metric,
+        threshold: isize,
+    ) -> Result<Option<(Point, Point)>, StarError> {
+        let (ul, lr) = bounding_box(points, 0, dims);
+        let (ul, lr) = (ul.coords(), lr.coords());
+        let cs = (0..3)
+            .map(|a| points.iter().map(|p| p.coords()[a]).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let minima = (0..3)
+            .map(|a| {
+                Ok(axis_totals(&cs[a], ul[a], lr[a], metric)?
+                    .into_iter()
+                    .zip(ul[a]..)
+                    .min()
+                    .expect("Expected points"))
+            })
+            .collect::<Result<Vec<_>, StarError>>()?;
+        let min_total = minima.iter().map(|(t, _)| t).sum::<isize>();
+        if min_total >= threshold {
+            return Ok(None);
         }
+
+        let mut lo = [0; 3];
+        let mut hi = [0; 3];
+        for a in 0..dims {
+            let (t, v) = minima[a];
+            let budget = threshold - (min_total - t);
+            lo[a] = v;
+            while axis_total(&cs[a], lo[a] - 1, metric).is_some_and(|t| t < budget) {
+                lo[a] -= 1;
+            }
+            hi[a] = v;
+            while axis_total(&cs[a], hi[a] + 1, metric).is_some_and(|t| t < budget) {
+                hi[a] += 1;
+            }
+        }
+        Ok(Some((
+            Point::new(lo[0], lo[1], lo[2]),
+            Point::new(hi[0], hi[1], hi[2]),
+        )))
+    }
+
+    fn area(&self) -> usize {
+        self.cells.iter().filter(|&&c| c).count()
     }
+
+    /// The outline of a 2D region, going clockwise around the locations' unit squares. The
+    /// region has to be one piece without holes, with every row being a single run.
+    fn polygon(&self) -> Result<Vec<(isize, isize)>, StarError> {
+        if self.dims != 2 {
+            return Err("Outlines are only available for 2D regions".into());
+        }
+        let mut rows = Vec::new();
+        for (y, row) in (self.ul.y..).zip(self.cells.chunks(self.size[0].max(1))) {
+            let safe = row.iter().filter(|&&c| c).count();
+            if let Some(l) = row.iter().position(|&c| c) {
+                if !row[l..l + safe].iter().all(|&c| c) {
+                    return Err(format!("Row {} is split into several runs", y).into());
+                }
+                let l = self.ul.x + l as isize;
+                rows.push((y, l, l + safe as isize));
+            }
+        }
+        // Consecutive rows have to share at least one column to be connected.
+        if let Some(w) = rows
+            .windows(2)
+            .find(|w| w[1].0 != w[0].0 + 1 || w[1].1 >= w[0].2 || w[0].1 >= w[1].2)
+        {
+            return Err(format!(
+                "Region is split into several pieces between rows {} and {}",
+                w[0].0, w[1].0
+            )
+            .into());
+        }
+
+        let mut outline = Vec::new();
+        for &(y, _, r) in &rows {
+            outline.push((r, y));
+            outline.push((r, y + 1));
+        }
+        for &(y, l, _) in rows.iter().rev() {
+            outline.push((l, y + 1));
+            outline.push((l, y));
+        }
+
+        // Drop corners that aren't, i.e. repeated vertices and those on a straight line.
+        let mut changed = true;
+        while changed && outline.len() > 2 {
+            changed = false;
+            let n = outline.len();
+            for i in 0..n {
+                let (p, c, q) = (outline[(i + n - 1) % n], outline[i], outline[(i + 1) % n]);
+                if c == q || (p.0 == c.0 && c.0 == q.0) || (p.1 == c.1 && c.1 == q.1) {
+                    outline.remove(i);
+                    changed = true;
+                    break;
+                }
+            }
+        }
+        Ok(outline)
+    }
+}
+
+impl Display for SafeRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [w, h, _] = self.size;
+        if w * h == 0 {
+            return Ok(());
+        }
+        for (z, slice) in self.cells.chunks(w * h).enumerate() {
+            if self.dims == 3 {
+                writeln!(f, "{},{},{}:", self.ul.x, self.ul.y, self.ul.z + z as isize)?;
+            } else {
+                writeln!(f, "{},{}:", self.ul.x, self.ul.y)?;
+            }
+            for row in slice.chunks(w) {
+                let row = row
+                    .iter()
+                    .map(|&c| if c { '#' } else { '.' })
+                    .collect::<String>();
+                writeln!(f, "{}", row)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Takes the distance limit from `--threshold=<n>`. `--shape=bitmap` prints the safe region,
+/// `--shape=polygon` its outline in WKT.
+pub fn star2(lines: impl Iterator<Item = std::io::Result<String>>, opts: &Options) -> StarResult {
+    let (points, dims) = parse_points(lines)?;
+    let metric = opts.value("metric", Metric::default())?;
+    let threshold = opts.value("threshold", 10000)?;
+
+    let region = SafeRegion::find(&points, dims, metric, threshold)?;
     println!(
-        "Size of the area of locations less then 10000 away is {}",
-        area
+        "Size of the area of locations less then {} away is {}",
+        threshold,
+        region.area()
     );
 
+    match opts.get("shape") {
+        Some("bitmap") => print!("{}", region),
+        Some("polygon") => {
+            let outline = region.polygon()?;
+            let vertices = outline
+                .iter()
+                .chain(outline.first())
+                .map(|(x, y)| format!("{} {}", x, y))
+                .join(", ");
+            println!("POLYGON (({}))", vertices);
+        }
+        Some(s) => return Err(format!("Unknown shape \"{}\"", s).into()),
+        None => (),
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn example() -> Vec<Point> {
+        parse_points(solve("src/day06/test").unwrap()).unwrap().0
+    }
+
+    fn parse(text: &str) -> (Vec<Point>, usize) {
+        parse_points(text.lines().map(|l| Ok(l.to_string()))).unwrap()
+    }
+
+    #[test]
+    fn regions() {
+        let points = example();
+        let regions = Regions::flood(&points, 2, Metric::Manhattan);
+        assert_eq!(
+            regions.infinite(&points),
+            vec![0, 1, 2, 5].into_iter().collect()
+        );
+        assert_eq!(regions.areas(points.len())[3..5], [9, 17]);
+        assert_eq!(
+            largest_finite_area(&points, 2, Metric::Manhattan),
+            Some((4, 17))
+        );
+        assert_eq!(
+            regions.to_string(),
+            "1,1:\naaaa.ccc\naaddeccc\nadddeccc\n.dddeecc\nb.deeeec\nbb.eeee.\nbb.eeeff\nbb.eefff\nbb.fffff\n"
+        );
+    }
+
+    #[test]
+    fn same_location() {
+        let (points, dims) = parse("0, 0\n2, 0\n2, 0");
+        let regions = Regions::flood(&points, dims, Metric::Manhattan);
+        assert_eq!(regions.areas(points.len()), vec![1, 0, 0]);
+        assert_eq!(regions.ties(), 2);
+    }
+
+    #[test]
+    fn metrics() {
+        let (a, b) = (Point::new(1, 2, 3), Point::new(4, 0, 3));
+        assert_eq!(Metric::Manhattan.dist(&a, &b), 5);
+        assert_eq!(Metric::Chebyshev.dist(&a, &b), 3);
+        assert_eq!(Metric::SquaredEuclidean.dist(&a, &b), 13);
+        assert_eq!(Metric::Manhattan.steps(3).unwrap().len(), 6);
+        assert_eq!(Metric::Chebyshev.steps(2).unwrap().len(), 8);
+
+        // The breadth first search agrees with comparing every location with every point.
+        let points = example();
+        for &metric in &[Metric::Manhattan, Metric::Chebyshev] {
+            let regions = Regions::flood(&points, 2, metric);
+            for (c, o) in regions.owners.iter().enumerate() {
+                assert_eq!(*o, closest_to(&points, &regions.location(c), metric));
+            }
+        }
+        let regions = Regions::flood(&points, 2, Metric::Chebyshev);
+        assert_eq!(
+            regions.infinite(&points),
+            vec![0, 1, 2, 5].into_iter().collect()
+        );
+    }
+
+    /// The areas of the finite regions, counted on a box `pad` larger than the bounding box
+    /// and checked to stay the same on a box twice that large.
+    fn brute_areas(points: &[Point], dims: usize, metric: Metric, pad: isize) -> Vec<usize> {
+        let count = |pad| {
+            let mut areas = vec![0; points.len()];
+            for p in within_box(bounding_box(points, pad, dims)) {
+                if let Owner::Point(i) = closest_to(points, &p, metric) {
+                    areas[i] += 1;
+                }
+            }
+            areas
+        };
+        let (areas, larger) = (count(pad), count(2 * pad));
+        areas
+            .into_iter()
+            .zip(larger)
+            .map(|(a, l)| if a == l { a } else { usize::MAX })
+            .collect()
+    }
+
+    #[test]
+    fn chebyshev_far_field() {
+        // Point 2 owns (5, 5) on the border and (5, 6) beyond it, but far out (0, 5) and
+        // (10, 5) take over, so its region is finite and the largest.
+        let (points, dims) = parse("0, 5\n10, 5\n5, 3\n5, 0");
+        let regions = Regions::flood(&points, dims, Metric::Chebyshev);
+        assert_eq!(
+            regions.infinite(&points),
+            vec![0, 1, 3].into_iter().collect()
+        );
+        let brute = brute_areas(&points, dims, Metric::Chebyshev, 30);
+        assert_eq!(regions.areas(points.len())[2], brute[2]);
+        assert_eq!(
+            largest_finite_area(&points, dims, Metric::Chebyshev),
+            Some((2, brute[2]))
+        );
+
+        // The middle point isn't the furthest out along any axis, but it is diagonally.
+        let (points, dims) = parse("0, 10\n10, 0\n6, 6\n0, 0");
+        let regions = Regions::flood(&points, dims, Metric::Chebyshev);
+        assert_eq!(regions.infinite(&points), (0..4).collect());
+
+        let (points, dims) = parse("2, 2, 2\n2, 2, 0\n2, 2, 4\n2, 0, 2\n2, 4, 2\n0, 2, 2\n4, 2, 2");
+        let regions = Regions::flood(&points, dims, Metric::Chebyshev);
+        let brute = brute_areas(&points, dims, Metric::Chebyshev, 10);
+        assert_eq!(regions.infinite(&points), (1..7).collect());
+        assert_eq!(regions.areas(points.len())[0], brute[0]);
+    }
+
+    #[test]
+    fn euclidean_hull() {
+        let points = example();
+        let regions = Regions::flood(&points, 2, Metric::SquaredEuclidean);
+        assert_eq!(
+            regions.infinite(&points),
+            vec![0, 1, 2, 5].into_iter().collect()
+        );
+        let brute = brute_areas(&points, 2, Metric::SquaredEuclidean, 30);
+        let areas = regions.areas(points.len());
+        assert_eq!(areas[3..5], brute[3..5]);
+
+        // The middle of a hull edge keeps the locations straight out from it.
+        let (points, dims) = parse("0, 0\n10, 0\n5, 0\n5, 20\n5, 1");
+        let regions = Regions::flood(&points, dims, Metric::SquaredEuclidean);
+        assert_eq!(regions.infinite(&points), (0..4).collect());
+
+        // Point 2 is inside the hull, but its region pokes out of the bounding box.
+        let (points, dims) = parse("0, 7\n1, 5\n4, 3\n7, 0\n6, 6");
+        let regions = Regions::flood(&points, dims, Metric::SquaredEuclidean);
+        assert_eq!(
+            regions.infinite(&points),
+            vec![0, 1, 3, 4].into_iter().collect()
+        );
+        let brute = brute_areas(&points, dims, Metric::SquaredEuclidean, 30);
+        let inside = within_box(bounding_box(&points, 0, dims))
+            .filter(|p| closest_to(&points, p, Metric::SquaredEuclidean) == Owner::Point(2))
+            .count();
+        assert!(inside < brute[2]);
+        assert_eq!(regions.areas(points.len())[2], brute[2]);
+
+        // All in a plane, so every region is infinite in 3D.
+        let (points, dims) = parse("0, 0, 1\n4, 0, 1\n0, 4, 1\n1, 1, 1");
+        let regions = Regions::flood(&points, dims, Metric::SquaredEuclidean);
+        assert_eq!(regions.infinite(&points), (0..4).collect());
+
+        let (points, dims) = parse("2, 2, 2\n2, 2, 0\n2, 2, 4\n2, 0, 2\n2, 4, 2\n0, 2, 2\n4, 2, 2");
+        let regions = Regions::flood(&points, dims, Metric::SquaredEuclidean);
+        assert_eq!(regions.infinite(&points), (1..7).collect());
+        assert_eq!(regions.areas(points.len())[0], 1);
+    }
+
+    #[test]
+    fn three_dimensions() {
+        let (points, dims) = parse("2, 2, 2\n2, 2, 0\n2, 2, 4\n2, 0, 2\n2, 4, 2\n0, 2, 2\n4, 2, 2");
+        assert_eq!(dims, 3);
+        let regions = Regions::flood(&points, dims, Metric::Manhattan);
+        for (c, o) in regions.owners.iter().enumerate() {
+            assert_eq!(
+                *o,
+                closest_to(&points, &regions.location(c), Metric::Manhattan)
+            );
+        }
+        assert_eq!(regions.infinite(&points), (1..7).collect());
+        assert_eq!(
+            largest_finite_area(&points, dims, Metric::Manhattan),
+            Some((0, 1))
+        );
+
+        assert!(
+            parse_points(vec![Ok("1, 2".to_string()), Ok("1, 2, 3".to_string())].into_iter())
+                .is_err()
+        );
+    }
+
+    /// Twice the area enclosed by a polygon.
+    fn shoelace(outline: &[(isize, isize)]) -> isize {
+        let n = outline.len();
+        (0..n)
+            .map(|i| {
+                let (a, b) = (outline[i], outline[(i + 1) % n]);
+                a.0 * b.1 - b.0 * a.1
+            })
+            .sum::<isize>()
+            .abs()
+    }
+
+    #[test]
+    fn safe_region() {
+        let points = example();
+        let region = SafeRegion::find(&points, 2, Metric::Manhattan, 32).unwrap();
+        assert_eq!(region.area(), 16);
+        assert_eq!(region.to_string(), "2,3:\n.###.\n#####\n#####\n.###.\n");
+        let outline = region.polygon().unwrap();
+        assert_eq!(outline.len(), 12);
+        assert_eq!(outline[0], (6, 3));
+        assert_eq!(shoelace(&outline), 2 * 16);
+
+        assert_eq!(
+            SafeRegion::find(&points, 2, Metric::Manhattan, 0)
+                .unwrap()
+                .area(),
+            0
+        );
+    }
+
+    #[test]
+    fn safe_region_is_complete() {
+        // The tight box is enough: fattening it by 10 on every side finds nothing more.
+        let points = example();
+        for &metric in &[
+            Metric::Manhattan,
+            Metric::Chebyshev,
+            Metric::SquaredEuclidean,
+        ] {
+            for &threshold in &[1, 32, 100, 300] {
+                let region = SafeRegion::find(&points, 2, metric, threshold).unwrap();
+                let brute = within_box(bounding_box(&points, 10 + threshold, 2))
+                    .filter(|p| {
+                        points.iter().map(|ps| metric.dist(ps, p)).sum::<isize>() < threshold
+                    })
+                    .count();
+                assert_eq!(region.area(), brute, "{:?} below {}", metric, threshold);
+            }
+        }
+
+        let (points, dims) = parse("0, 0, 0\n3, 1, 2\n1, 4, 1");
+        let region = SafeRegion::find(&points, dims, Metric::Manhattan, 20).unwrap();
+        let brute = within_box(bounding_box(&points, 20, dims))
+            .filter(|p| {
+                points
+                    .iter()
+                    .map(|ps| Metric::Manhattan.dist(ps, p))
+                    .sum::<isize>()
+                    < 20
+            })
+            .count();
+        assert_eq!(region.area(), brute);
+        assert!(region.polygon().is_err());
+    }
+
+    #[test]
+    fn safe_region_edge_cases() {
+        let (points, dims) = parse("0, 0, 0\n3, 1, 2\n1, 4, 1");
+        let region = SafeRegion::find(&points, dims, Metric::Chebyshev, 12).unwrap();
+        let brute = within_box(bounding_box(&points, 12, dims))
+            .filter(|p| {
+                points
+                    .iter()
+                    .map(|ps| Metric::Chebyshev.dist(ps, p))
+                    .sum::<isize>()
+                    < 12
+            })
+            .count();
+        assert_eq!(region.area(), brute);
+
+        // Two locations touching only at a corner.
+        let diagonal = SafeRegion {
+            ul: Point::default(),
+            size: [2, 2, 1],
+            dims: 2,
+            cells: vec![true, false, false, true],
+        };
+        assert!(diagonal.polygon().is_err());
+
+        let (points, dims) = parse("3000000000, 0\n-3000000000, 0");
+        assert!(SafeRegion::find(&points, dims, Metric::SquaredEuclidean, 10).is_err());
+    }
+}
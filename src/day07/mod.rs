@@ -1,32 +1,19 @@
-use std::cmp::{Ordering, PartialOrd};
+use std::cmp::{max, min, Ordering, Reverse};
 use std::collections::{BTreeSet, BinaryHeap, HashMap};
 use std::io;
-use std::iter::FromIterator;
+
+use itertools::Itertools;
 
 use super::*;
 
-type Deps = BTreeSet<char>;
+type Deps = BTreeSet<String>;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 struct Node {
-    name: char,
+    name: String,
     dep: Deps,
 }
 
-impl PartialOrd for Node {
-    fn partial_cmp(&self, other: &Node) -> Option<Ordering> {
-        if self.dep == other.dep {
-            Some(self.name.cmp(&other.name))
-        } else if self.dep.contains(&other.name) {
-            Some(Ordering::Greater)
-        } else if other.dep.contains(&self.name) {
-            Some(Ordering::Less)
-        } else {
-            None
-        }
-    }
-}
-
 impl Display for Node {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{} -> ", self.name)?;
@@ -40,25 +27,228 @@ impl Display for Node {
 }
 
 impl Node {
-    fn new(name: char) -> Node {
+    fn new(name: &str) -> Node {
         Node {
-            name,
+            name: name.to_string(),
             dep: BTreeSet::new(),
         }
     }
     //    fn with_deps(name: char, deps: &Vec<char>) -> Node {
     //        Node{name: name, dep: deps.clone()}
     //    }
-    fn add(&mut self, dep: char) {
-        self.dep.insert(dep);
+    fn add(&mut self, dep: &str) {
+        self.dep.insert(dep.to_string());
+    }
+}
+
+/// The formats the instructions can be given in. All of them list which steps have to be
+/// finished before others can begin:
+///
+/// * `aoc`: `Step A must be finished before step B can begin.`
+/// * `edges`: `A -> B`, with chains like `A -> B -> C` and single steps allowed
+/// * `make`: `B: A`, targets depending on prerequisites like in a Makefile
+/// * `json`: `{"A": ["B"]}`, every step mapped to the steps waiting for it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Aoc,
+    Edges,
+    Make,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = StarError;
+    fn from_str(s: &str) -> Result<Format, StarError> {
+        match s {
+            "aoc" => Ok(Format::Aoc),
+            "edges" => Ok(Format::Edges),
+            "make" => Ok(Format::Make),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Unknown input format \"{}\"", s).into()),
+        }
+    }
+}
+
+impl Format {
+    /// Guesses the format from the first line with content, passing over Makefile variable
+    /// assignments.
+    fn detect(lines: &[String]) -> Format {
+        let first = lines
+            .iter()
+            .map(|l| l.trim())
+            .filter(|l| !is_assignment(l))
+            .find(|l| !l.is_empty() && !l.starts_with('#'))
+            .unwrap_or_default();
+        if first.starts_with('{') {
+            Format::Json
+        } else if first.starts_with("Step ") {
+            Format::Aoc
+        } else if first.contains("->") || !first.contains(':') {
+            Format::Edges
+        } else {
+            Format::Make
+        }
+    }
+
+    fn from_options(opts: &super::Options) -> Result<Option<Format>, StarError> {
+        opts.get("format").map(str::parse).transpose()
     }
-    fn satisfied(&self, deps: impl Iterator<Item = char>) -> bool {
-        self.dep.is_subset(&BTreeSet::from_iter(deps))
+}
+
+/// Whether the line is a Makefile variable assignment like `CC = gcc` or `LD := ld`: a variable
+/// name at the start of the line followed by one of make's assignment operators.
+fn is_assignment(line: &str) -> bool {
+    let rest = line.trim_start_matches(|c: char| c.is_ascii_alphanumeric() || c == '_');
+    if rest.len() == line.len() {
+        return false;
     }
+    let rest = rest.trim_start();
+    ["=", ":=", "::=", "?=", "+=", "!="]
+        .iter()
+        .any(|op| rest.starts_with(op))
+}
+
+/// A parser for the part of JSON used by adjacency lists: an object mapping names to arrays of
+/// names.
+struct Json<'a> {
+    s: &'a str,
+    pos: usize,
 }
 
+impl<'a> Json<'a> {
+    fn peek(&self) -> Option<char> {
+        self.s[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn error(&self, what: &str) -> StarError {
+        format!("JSON at offset {}: expected {}", self.pos, what).into()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), StarError> {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.error(&format!("'{}'", c)))
+        }
+    }
+
+    /// Parses a comma separated list up to `close`, calling `item` for every element.
+    fn list(
+        &mut self,
+        close: char,
+        mut item: impl FnMut(&mut Self) -> Result<(), StarError>,
+    ) -> StarResult {
+        self.skip_whitespace();
+        if self.peek() == Some(close) {
+            self.next();
+            return Ok(());
+        }
+        loop {
+            item(self)?;
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(c) if c == close => return Ok(()),
+                _ => return Err(self.error(&format!("',' or '{}'", close))),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, StarError> {
+        self.expect('"')?;
+        let mut ret = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(ret),
+                Some('\\') => match self.next() {
+                    Some('n') => ret.push('\n'),
+                    Some('t') => ret.push('\t'),
+                    Some('r') => ret.push('\r'),
+                    Some('b') => ret.push('\u{8}'),
+                    Some('f') => ret.push('\u{c}'),
+                    Some('u') => ret.push(self.unicode_escape()?),
+                    Some(c) if "\"\\/".contains(c) => ret.push(c),
+                    _ => return Err(self.error("an escape sequence")),
+                },
+                Some(c) => ret.push(c),
+                None => return Err(self.error("'\"'")),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, StarError> {
+        let hex = self.s.get(self.pos..self.pos + 4).unwrap_or_default();
+        if hex.len() != 4 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(self.error("four hex digits"));
+        }
+        let n = u32::from_str_radix(hex, 16)?;
+        self.pos += 4;
+        Ok(n)
+    }
+
+    /// Decodes the code point after `\u`, combining a UTF-16 surrogate pair written as two
+    /// escapes like `\uD83D\uDE00`.
+    fn unicode_escape(&mut self) -> Result<char, StarError> {
+        let high = self.hex4()?;
+        let c = match high {
+            0xD800..=0xDBFF => {
+                if self.s.get(self.pos..self.pos + 2) != Some("\\u") {
+                    return Err(self.error("a low surrogate after a high surrogate"));
+                }
+                self.pos += 2;
+                let low = self.hex4()?;
+                if !(0xDC00..=0xDFFF).contains(&low) {
+                    return Err(self.error("a low surrogate after a high surrogate"));
+                }
+                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+            }
+            0xDC00..=0xDFFF => return Err(self.error("a high surrogate before a low surrogate")),
+            c => c,
+        };
+        Ok(std::char::from_u32(c).expect("Surrogates are handled above"))
+    }
+
+    fn adjacency(s: &str) -> Result<Vec<(String, Vec<String>)>, StarError> {
+        let mut json = Json { s, pos: 0 };
+        let mut ret = Vec::new();
+        json.expect('{')?;
+        json.list('}', |json| {
+            let name = json.string()?;
+            json.expect(':')?;
+            json.expect('[')?;
+            let mut next = Vec::new();
+            json.list(']', |json| {
+                next.push(json.string()?);
+                Ok(())
+            })?;
+            ret.push((name, next));
+            Ok(())
+        })?;
+        json.skip_whitespace();
+        if json.pos < s.len() {
+            return Err(json.error("the end of the input"));
+        }
+        Ok(ret)
+    }
+}
+
+#[derive(Debug)]
 struct Graph {
-    edges: HashMap<char, Node>,
+    edges: HashMap<String, Node>,
 }
 
 impl Graph {
@@ -68,93 +258,459 @@ impl Graph {
         }
     }
 
-    fn build(lines: impl Iterator<Item = io::Result<String>>) -> Result<Graph, StarError> {
+    /// Parses the instructions, in the given format or the detected one, and makes sure every
+    /// step can eventually begin.
+    fn build(
+        lines: impl Iterator<Item = io::Result<String>>,
+        format: Option<Format>,
+    ) -> Result<Graph, StarError> {
+        let g = Self::parse(lines, format)?;
+        g.validate()?;
+        Ok(g)
+    }
+
+    fn parse(
+        lines: impl Iterator<Item = io::Result<String>>,
+        format: Option<Format>,
+    ) -> Result<Graph, StarError> {
+        let lines = lines.collect::<io::Result<Vec<_>>>()?;
         let mut g = Self::new();
-        for r in lines.map(|l| l.map_err(StarError::from)) {
-            let s = r?;
-            let w: Vec<_> = s.split_whitespace().collect();
-            if w.len() != 10 {
-                return Err(format!("Wrong number of words: \"{:?}\"", w).into());
-            }
-            let dep = w[1].chars().next().expect("Expected single char dep name");
-            let node = w[7].chars().next().expect("Expected single char dep name");
-            g.add(dep, node);
+        let format = format.unwrap_or_else(|| Format::detect(&lines));
+        if format == Format::Json {
+            for (name, next) in Json::adjacency(&lines.join("\n"))? {
+                g.add_step(&name);
+                for n in next {
+                    g.add(&name, &n);
+                }
+            }
+            return Ok(g);
+        }
+
+        for (i, s) in (1..).zip(lines.iter()) {
+            let s = s.trim();
+            if s.is_empty() || s.starts_with('#') {
+                continue;
+            }
+            match format {
+                Format::Aoc => {
+                    let w: Vec<_> = s.split_whitespace().collect();
+                    if w.len() != 10 {
+                        return Err(
+                            format!("Line {}: wrong number of words: \"{:?}\"", i, w).into()
+                        );
+                    }
+                    g.add(w[1], w[7]);
+                }
+                Format::Edges => {
+                    let steps = s.split("->").map(str::trim).collect::<Vec<_>>();
+                    if steps
+                        .iter()
+                        .any(|n| n.is_empty() || n.contains(char::is_whitespace))
+                    {
+                        return Err(
+                            format!("Line {}: expected \"A -> B\", got \"{}\"", i, s).into()
+                        );
+                    }
+                    g.add_step(steps[0]);
+                    for w in steps.windows(2) {
+                        g.add(w[0], w[1]);
+                    }
+                }
+                Format::Make => {
+                    // Recipes and variable assignments don't add dependencies.
+                    if lines[i - 1].starts_with(char::is_whitespace) || is_assignment(s) {
+                        continue;
+                    }
+                    let mut parts = s.splitn(2, ':');
+                    let targets = parts.next().unwrap_or_default();
+                    let deps = parts.next().ok_or_else(|| {
+                        format!("Line {}: expected \"target: deps\", got \"{}\"", i, s)
+                    })?;
+                    // Special targets like `.PHONY` aren't steps.
+                    for target in targets.split_whitespace().filter(|t| !t.starts_with('.')) {
+                        g.add_step(target);
+                        for dep in deps.split_whitespace().filter(|&d| d != "|") {
+                            g.add(dep, target);
+                        }
+                    }
+                }
+                Format::Json => unreachable!(),
+            }
         }
         Ok(g)
     }
 
-    fn add(&mut self, dep: char, node: char) {
-        self.edges.entry(dep).or_insert_with(|| Node::new(dep));
-        let v = self.edges.entry(node).or_insert_with(|| Node::new(node));
-        v.add(dep);
+    fn add_step(&mut self, name: &str) {
+        if !self.edges.contains_key(name) {
+            self.edges.insert(name.to_string(), Node::new(name));
+        }
+    }
+
+    fn add(&mut self, dep: &str, node: &str) {
+        self.add_step(dep);
+        self.add_step(node);
+        self.edges.get_mut(node).unwrap().add(dep);
+    }
+
+    fn validate(&self) -> Result<(), StarError> {
+        if self.is_acyclic() {
+            return Ok(());
+        }
+        let cycle = self.cycle().expect("Cyclic graph without a cycle");
+        Err(format!(
+            "Steps form a cycle: {} -> {}. Steps that can never begin: {}",
+            cycle.iter().join(" -> "),
+            cycle[0],
+            self.unsatisfiable().iter().join(", ")
+        )
+        .into())
+    }
+
+    fn is_acyclic(&self) -> bool {
+        self.components()
+            .iter()
+            .all(|c| c.len() == 1 && !self.edges[c[0]].dep.contains(c[0]))
+    }
+
+    /// The strongly connected components, found with Tarjan's algorithm. Steps in a component
+    /// with more than one step, or one depending on itself, are part of a cycle. Components
+    /// come in dependency order, every one sorted by name.
+    fn components(&self) -> Vec<Vec<&str>> {
+        let mut names = self.edges.keys().map(String::as_str).collect::<Vec<_>>();
+        names.sort();
+
+        let mut index: HashMap<&str, usize> = HashMap::new();
+        let mut lowlink: HashMap<&str, usize> = HashMap::new();
+        let mut stack = Vec::new();
+        let mut on_stack = BTreeSet::new();
+        let mut components = Vec::new();
+
+        for &root in &names {
+            if index.contains_key(&root) {
+                continue;
+            }
+            // Every frame is a step and the dependencies still to visit.
+            let mut frames = vec![(root, self.deps(root))];
+            index.insert(root, index.len());
+            lowlink.insert(root, index[&root]);
+            stack.push(root);
+            on_stack.insert(root);
+
+            while let Some((n, deps)) = frames.last_mut() {
+                let n = *n;
+                if let Some(d) = deps.pop() {
+                    if !index.contains_key(&d) {
+                        index.insert(d, index.len());
+                        lowlink.insert(d, index[&d]);
+                        stack.push(d);
+                        on_stack.insert(d);
+                        frames.push((d, self.deps(d)));
+                    } else if on_stack.contains(&d) {
+                        let low = min(lowlink[&n], index[&d]);
+                        lowlink.insert(n, low);
+                    }
+                    continue;
+                }
+
+                frames.pop();
+                if let Some((parent, _)) = frames.last() {
+                    let low = min(lowlink[parent], lowlink[&n]);
+                    lowlink.insert(*parent, low);
+                }
+                if lowlink[&n] == index[&n] {
+                    let mut component = Vec::new();
+                    while let Some(m) = stack.pop() {
+                        on_stack.remove(&m);
+                        component.push(m);
+                        if m == n {
+                            break;
+                        }
+                    }
+                    component.sort();
+                    components.push(component);
+                }
+            }
+        }
+        components
+    }
+
+    fn deps(&self, name: &str) -> Vec<&str> {
+        self.edges[name].dep.iter().map(String::as_str).collect()
+    }
+
+    /// A cycle of steps, each of which has to be finished before the next one can begin, and
+    /// the last one before the first one.
+    fn cycle(&self) -> Option<Vec<&str>> {
+        let component = self
+            .components()
+            .into_iter()
+            .find(|c| c.len() > 1 || self.edges[c[0]].dep.contains(c[0]))?;
+        let members = component.iter().cloned().collect::<BTreeSet<_>>();
+
+        // Follow dependencies within the component until a step comes up again.
+        let mut path = vec![component[0]];
+        loop {
+            let last = path[path.len() - 1];
+            let next = self
+                .deps(last)
+                .into_iter()
+                .find(|d| members.contains(d))
+                .expect("Every step of a cycle depends on another one");
+            if let Some(start) = path.iter().position(|&n| n == next) {
+                let mut cycle = path.split_off(start);
+                cycle.reverse();
+                let first = (0..cycle.len()).min_by_key(|&i| cycle[i]).unwrap();
+                cycle.rotate_left(first);
+                return Some(cycle);
+            }
+            path.push(next);
+        }
+    }
+
+    /// The steps that can never begin, because they are part of a cycle or depend on one.
+    fn unsatisfiable(&self) -> Vec<&str> {
+        let indexed = Indexed::new(self);
+        let mut done = vec![false; indexed.names.len()];
+        for i in indexed.kahn() {
+            done[i] = true;
+        }
+        (0..done.len())
+            .filter(|&i| !done[i])
+            .map(|i| indexed.names[i])
+            .collect()
     }
 }
 impl Display for Graph {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for v in self.edges.values() {
+        for v in self.edges.values().sorted_by_key(|v| &v.name) {
             writeln!(f, "{}", v)?;
         }
         Ok(())
     }
 }
 
-fn next_work(todo: &[Node], done: &[Node]) -> Option<usize> {
-    todo.iter()
-        .enumerate()
-        .filter(|(_, m)| m.satisfied(done.iter().map(|n| n.name)))
-        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or_else(|| a.name.cmp(&b.name)))
-        .map(|(i, _)| i)
+impl Graph {
+    /// The graph in Graphviz DOT format, with the steps in topological order. Given the
+    /// durations and a schedule, every step is labelled with its position in the order, its
+    /// duration and the worker and time it was worked on.
+    fn dot(&self, annotations: Option<(&Durations, &Schedule)>) -> Result<String, StarError> {
+        let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+        let quote = |s: &str| format!("\"{}\"", escape(s));
+        let order = toposort(self)?;
+        let mut ret = String::from("digraph steps {\n    rankdir=LR;\n    node [shape=box];\n");
+        for (i, &step) in order.iter().enumerate() {
+            match annotations {
+                Some((durations, schedule)) => {
+                    let slot = schedule
+                        .slots
+                        .iter()
+                        .find(|s| s.step == step)
+                        .ok_or_else(|| format!("Step {} isn't scheduled", step))?;
+                    let label = format!(
+                        "{}\\n#{}, {}s\\nworker {}, {}-{}",
+                        escape(step),
+                        i + 1,
+                        durations.of(step),
+                        slot.worker + 1,
+                        slot.start,
+                        slot.end
+                    );
+                    ret += &format!("    {} [label=\"{}\"];\n", quote(step), label);
+                }
+                None => ret += &format!("    {};\n", quote(step)),
+            }
+        }
+        for &step in &order {
+            for dep in &self.edges[step].dep {
+                ret += &format!("    {} -> {};\n", quote(dep), quote(step));
+            }
+        }
+        ret += "}\n";
+        Ok(ret)
+    }
 }
 
-fn toposort(v: &[Node]) -> Vec<Node> {
-    let mut done: Vec<Node> = Vec::new();
-    let mut todo = v.to_owned();
-    while !todo.is_empty() {
-        let m = next_work(&todo, &done);
+/// The steps numbered in name order, so the smallest number among the ready steps is the one
+/// to do first.
+struct Indexed<'a> {
+    names: Vec<&'a str>,
+    next: Vec<Vec<usize>>,
+    indegree: Vec<usize>,
+}
+
+impl<'a> Indexed<'a> {
+    fn new(g: &'a Graph) -> Indexed<'a> {
+        let mut names = g.edges.keys().map(String::as_str).collect::<Vec<_>>();
+        names.sort();
+        let index: HashMap<&str, usize> = names.iter().enumerate().map(|(i, &n)| (n, i)).collect();
+
+        let mut next = vec![Vec::new(); names.len()];
+        let mut indegree = vec![0; names.len()];
+        for (i, n) in names.iter().enumerate() {
+            for d in &g.edges[*n].dep {
+                next[index[d.as_str()]].push(i);
+                indegree[i] += 1;
+            }
+        }
+        Indexed {
+            names,
+            next,
+            indegree,
+        }
+    }
+
+    /// The steps that can begin right away.
+    fn ready(&self) -> BinaryHeap<Reverse<usize>> {
+        (0..self.names.len())
+            .filter(|&i| self.indegree[i] == 0)
+            .map(Reverse)
+            .collect()
+    }
 
-        match m {
-            Some(i) => {
-                let n = todo.swap_remove(i);
-                done.push(n);
+    /// Marks a step as done, adding the steps waiting only for it to the ready queue.
+    fn finish(&self, step: usize, indegree: &mut [usize], ready: &mut BinaryHeap<Reverse<usize>>) {
+        for &n in &self.next[step] {
+            indegree[n] -= 1;
+            if indegree[n] == 0 {
+                ready.push(Reverse(n));
             }
-            None => panic!("Loop?"),
         }
     }
-    done
+
+    /// Kahn's algorithm, always taking the smallest ready step. Steps that never get ready
+    /// are left out.
+    fn kahn(&self) -> Vec<usize> {
+        let mut indegree = self.indegree.clone();
+        let mut ready = self.ready();
+        let mut order = Vec::with_capacity(self.names.len());
+        while let Some(Reverse(i)) = ready.pop() {
+            order.push(i);
+            self.finish(i, &mut indegree, &mut ready);
+        }
+        order
+    }
+}
+
+fn toposort(g: &Graph) -> Result<Vec<&str>, StarError> {
+    let indexed = Indexed::new(g);
+    let order = indexed.kahn();
+    if order.len() < indexed.names.len() {
+        return Err(g.validate().expect_err("Stuck without a cycle"));
+    }
+    Ok(order.into_iter().map(|i| indexed.names[i]).collect())
+}
+
+/// Step names run together when they are single letters, like in the puzzle, and are
+/// separated by commas otherwise.
+fn join_names<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    let names = names.collect::<Vec<_>>();
+    if names.iter().all(|n| n.chars().count() == 1) {
+        names.concat()
+    } else {
+        names.join(",")
+    }
 }
 
-pub fn star1(lines: impl Iterator<Item = io::Result<String>>) -> StarResult {
-    let g = Graph::build(lines)?;
-    let v: Vec<Node> = g.edges.values().cloned().collect();
-    let v = toposort(&v);
-    println!("{}", v.iter().map(|n| n.name).collect::<String>());
+/// Reads the instructions in the format given by `--format=aoc|edges|make|json`, or detects it.
+pub fn star1(lines: impl Iterator<Item = io::Result<String>>, opts: &super::Options) -> StarResult {
+    let g = Graph::build(lines, Format::from_options(opts)?)?;
+    let v = toposort(&g)?;
+    println!("{}", join_names(v.into_iter()));
     Ok(())
 }
 
+/// How long every step takes: the base duration plus the position of the step in the
+/// alphabet for single capital letters, or plus one for other names, unless a duration file
+/// overrides it. The file has a step and its duration per line, e.g. `A 5`.
+#[derive(Debug, Default)]
+struct Durations {
+    base: usize,
+    overrides: HashMap<String, usize>,
+}
+
+impl Durations {
+    fn new(base: usize) -> Durations {
+        Durations {
+            base,
+            overrides: HashMap::new(),
+        }
+    }
+
+    fn parse(
+        lines: impl Iterator<Item = io::Result<String>>,
+        base: usize,
+    ) -> Result<Durations, StarError> {
+        let mut durations = Self::new(base);
+        for (i, l) in (1..).zip(lines) {
+            let l = l?;
+            let w: Vec<_> = l.split_whitespace().collect();
+            match w[..] {
+                [] => continue,
+                [step, t] => {
+                    let t = t.parse().map_err(|e| {
+                        StarError::from(format!("Line {}: bad duration \"{}\": {}", i, t, e))
+                    })?;
+                    durations.overrides.insert(step.to_string(), t);
+                }
+                _ => return Err(format!("Line {}: expected a step and a duration", i).into()),
+            }
+        }
+        Ok(durations)
+    }
+
+    fn of(&self, step: &str) -> usize {
+        if let Some(&t) = self.overrides.get(step) {
+            return t;
+        }
+        match step.as_bytes() {
+            [c @ b'A'..=b'Z'] => self.base + (c - b'A') as usize + 1,
+            _ => self.base + 1,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug)]
 struct Work {
-    node: Node,
+    step: usize,
+    worker: usize,
     t: usize,
+    duration: usize,
 }
 
 impl Work {
-    fn new(n: Node, starttime: usize) -> Work {
+    fn new(
+        step: usize,
+        name: &str,
+        worker: usize,
+        starttime: usize,
+        durations: &Durations,
+    ) -> Work {
         Work {
-            node: n,
+            step,
+            duration: durations.of(name),
+            worker,
             t: starttime,
         }
     }
     fn finish_in(&self) -> usize {
-        self.t + self.worksize()
+        self.t + self.duration
     }
-    fn worksize(&self) -> usize {
-        self.node.name as usize - 'A' as usize + 61
+    fn slot(&self, names: &[&str]) -> Slot {
+        Slot {
+            worker: self.worker,
+            step: names[self.step].to_string(),
+            start: self.t,
+            end: self.finish_in(),
+        }
     }
 }
 
 impl Ord for Work {
     fn cmp(&self, other: &Work) -> Ordering {
-        other.finish_in().cmp(&self.finish_in())
+        other
+            .finish_in()
+            .cmp(&self.finish_in())
+            .then_with(|| other.step.cmp(&self.step))
     }
 }
 impl PartialOrd for Work {
@@ -163,41 +719,827 @@ impl PartialOrd for Work {
     }
 }
 
-fn parallel_topo(v: &[Node], worker: usize) -> (Vec<Node>, usize) {
+/// A step worked on by a worker from second `start` until it's done at `end`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Slot {
+    worker: usize,
+    step: String,
+    start: usize,
+    end: usize,
+}
+
+/// Who worked on which step when, with the slots in the order the steps got done.
+#[derive(Debug)]
+struct Schedule {
+    workers: usize,
+    slots: Vec<Slot>,
+    time: usize,
+}
+
+impl Schedule {
+    fn order(&self) -> String {
+        join_names(self.slots.iter().map(|s| s.step.as_str()))
+    }
+
+    /// The periods in which a worker had nothing to do, as (worker, start, end).
+    fn idle(&self) -> Vec<(usize, usize, usize)> {
+        let mut idle = Vec::new();
+        for w in 0..self.workers {
+            let mut t = 0;
+            for s in self
+                .slots
+                .iter()
+                .filter(|s| s.worker == w)
+                .sorted_by_key(|s| s.start)
+            {
+                if s.start > t {
+                    idle.push((w, t, s.start));
+                }
+                t = s.end;
+            }
+            if self.time > t {
+                idle.push((w, t, self.time));
+            }
+        }
+        idle
+    }
+
+    fn running(&self, worker: usize, second: usize) -> Option<&str> {
+        self.slots
+            .iter()
+            .find(|s| s.worker == worker && s.start <= second && second < s.end)
+            .map(|s| s.step.as_str())
+    }
+
+    fn done(&self, second: usize) -> String {
+        join_names(
+            self.slots
+                .iter()
+                .filter(|s| s.end <= second)
+                .map(|s| s.step.as_str()),
+        )
+    }
+
+    /// The steps that could begin at the given second, but are waiting for a worker.
+    fn ready(&self, g: &Graph, second: usize) -> String {
+        let end: HashMap<&str, usize> = self
+            .slots
+            .iter()
+            .map(|s| (s.step.as_str(), s.end))
+            .collect();
+        let ready = self
+            .slots
+            .iter()
+            .filter(|s| s.start > second)
+            .filter(|s| {
+                g.edges[&s.step]
+                    .dep
+                    .iter()
+                    .all(|d| end[d.as_str()] <= second)
+            })
+            .map(|s| s.step.as_str())
+            .sorted();
+        join_names(ready.into_iter())
+    }
+
+    /// The second by second table from the puzzle description, with the ready queue added.
+    fn table(&self, g: &Graph) -> String {
+        let longest = self.slots.iter().map(|s| s.step.len()).max().unwrap_or(0);
+        let cell = |w: usize| max(7 + (w + 1).to_string().len(), longest);
+        let done = max(self.done(self.time).len(), 4);
+
+        let mut ret = String::from("Second");
+        for w in 0..self.workers {
+            ret += &format!("   {:^w$}", format!("Worker {}", w + 1), w = cell(w));
+        }
+        ret += &format!("   {:w$}   Ready\n", "Done", w = done);
+        for t in 0..=self.time {
+            let mut row = format!("{:>6}", t);
+            for w in 0..self.workers {
+                let step = self.running(w, t).unwrap_or(".");
+                row += &format!("   {:^w$}", step, w = cell(w));
+            }
+            row += &format!("   {:w$}", self.done(t), w = done);
+            row += &format!("   {}", self.ready(g, t));
+            ret += row.trim_end();
+            ret += "\n";
+        }
+        ret
+    }
+
+    fn csv(&self) -> String {
+        let mut ret = String::from("kind,worker,step,start,end\n");
+        for s in &self.slots {
+            let step = if s.step.contains(&[',', '"'][..]) {
+                format!("\"{}\"", s.step.replace('"', "\"\""))
+            } else {
+                s.step.clone()
+            };
+            ret += &format!("work,{},{},{},{}\n", s.worker + 1, step, s.start, s.end);
+        }
+        for (w, start, end) in self.idle() {
+            ret += &format!("idle,{},,{},{}\n", w + 1, start, end);
+        }
+        ret
+    }
+
+    /// A Gantt chart with a row per worker, scaled to be about 800 pixels wide.
+    fn svg(&self) -> String {
+        const LEFT: f64 = 80.0;
+        const ROW: f64 = 24.0;
+        const WIDTH: f64 = 800.0;
+        let scale = WIDTH / self.time.max(1) as f64;
+        let x = |t: usize| LEFT + t as f64 * scale;
+        let y = |w: usize| 10.0 + w as f64 * ROW;
+        let axis = y(self.workers) + 4.0;
+
+        let mut ret = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             font-family=\"monospace\" font-size=\"12\">\n",
+            LEFT + WIDTH + 20.0,
+            axis + 30.0
+        );
+        for w in 0..self.workers {
+            ret += &format!(
+                "<text x=\"4\" y=\"{}\">Worker {}</text>\n",
+                y(w) + ROW / 2.0 + 4.0,
+                w + 1
+            );
+        }
+        for (w, start, end) in self.idle() {
+            ret += &format!(
+                "<rect x=\"{:.1}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" fill=\"#eeeeee\">\
+                 <title>idle {}-{}</title></rect>\n",
+                x(start),
+                y(w) + 2.0,
+                (end - start) as f64 * scale,
+                ROW - 4.0,
+                start,
+                end
+            );
+        }
+        for s in &self.slots {
+            ret += &format!(
+                "<rect x=\"{:.1}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" fill=\"#8ab4f8\" \
+                 stroke=\"#1a4d99\"><title>{} {}-{}</title></rect>\n",
+                x(s.start),
+                y(s.worker) + 2.0,
+                (s.end - s.start) as f64 * scale,
+                ROW - 4.0,
+                escape(&s.step),
+                s.start,
+                s.end
+            );
+            ret += &format!(
+                "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n",
+                (x(s.start) + x(s.end)) / 2.0,
+                y(s.worker) + ROW / 2.0 + 4.0,
+                escape(&s.step)
+            );
+        }
+
+        // A time axis with at most 20 ticks.
+        ret += &format!(
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"black\"/>\n",
+            x(0),
+            axis,
+            x(self.time),
+            axis
+        );
+        let tick = [1, 2, 5]
+            .iter()
+            .cycle()
+            .zip((0..).map(|e| 10usize.pow(e / 3)))
+            .map(|(m, p)| m * p)
+            .find(|&t| self.time / t <= 20)
+            .unwrap();
+        for t in (0..=self.time).step_by(tick) {
+            ret += &format!(
+                "<line x1=\"{0:.1}\" y1=\"{1}\" x2=\"{0:.1}\" y2=\"{2}\" stroke=\"black\"/>\
+                 <text x=\"{0:.1}\" y=\"{3}\" text-anchor=\"middle\">{4}</text>\n",
+                x(t),
+                axis,
+                axis + 4.0,
+                axis + 18.0,
+                t
+            );
+        }
+        ret += "</svg>\n";
+        ret
+    }
+}
+
+/// When a step can start and finish at the earliest, and at the latest without delaying the
+/// end of the project when there are always enough workers.
+#[derive(Debug, PartialEq, Eq)]
+struct Timing {
+    step: String,
+    duration: usize,
+    earliest_start: usize,
+    earliest_finish: usize,
+    latest_start: usize,
+    latest_finish: usize,
+}
+
+impl Timing {
+    fn slack(&self) -> usize {
+        self.latest_start - self.earliest_start
+    }
+}
+
+/// The critical path method: the timings of all steps in topological order, the time needed
+/// with unlimited workers and the longest chain of steps without slack.
+#[derive(Debug)]
+struct Analysis {
+    timings: Vec<Timing>,
+    length: usize,
+    critical: Vec<String>,
+}
+
+impl Analysis {
+    fn new(g: &Graph, durations: &Durations) -> Result<Analysis, StarError> {
+        let indexed = Indexed::new(g);
+        let order = indexed.kahn();
+        if order.len() < indexed.names.len() {
+            return Err(g.validate().expect_err("Stuck without a cycle"));
+        }
+        let n = indexed.names.len();
+        let duration = indexed
+            .names
+            .iter()
+            .map(|s| durations.of(s))
+            .collect::<Vec<_>>();
+
+        // Forward pass: a step starts as soon as all its dependencies are finished.
+        let mut es = vec![0; n];
+        for &i in &order {
+            for &m in &indexed.next[i] {
+                es[m] = max(es[m], es[i] + duration[i]);
+            }
+        }
+        let length = (0..n).map(|i| es[i] + duration[i]).max().unwrap_or(0);
+
+        // Backward pass: a step finishes before any step waiting for it has to start.
+        let mut lf = vec![length; n];
+        for &i in order.iter().rev() {
+            for &m in &indexed.next[i] {
+                lf[i] = min(lf[i], lf[m] - duration[m]);
+            }
+        }
+
+        // Follow steps without slack from the start, each one finishing as the next begins.
+        let tight = |i: usize| es[i] + duration[i] == lf[i];
+        let mut critical = Vec::new();
+        let mut current = (0..n).find(|&i| es[i] == 0 && tight(i));
+        while let Some(i) = current {
+            critical.push(indexed.names[i].to_string());
+            current = indexed.next[i]
+                .iter()
+                .cloned()
+                .filter(|&m| tight(m) && es[m] == es[i] + duration[i])
+                .min();
+        }
+
+        let timings = order
+            .into_iter()
+            .map(|i| Timing {
+                step: indexed.names[i].to_string(),
+                duration: duration[i],
+                earliest_start: es[i],
+                earliest_finish: es[i] + duration[i],
+                latest_start: lf[i] - duration[i],
+                latest_finish: lf[i],
+            })
+            .collect();
+        Ok(Analysis {
+            timings,
+            length,
+            critical,
+        })
+    }
+}
+
+impl Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let w = self
+            .timings
+            .iter()
+            .map(|t| t.step.len())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+        writeln!(
+            f,
+            "{:w$} {:>8} {:>6} {:>6} {:>6} {:>6} {:>6}",
+            "Step",
+            "Duration",
+            "ES",
+            "EF",
+            "LS",
+            "LF",
+            "Slack",
+            w = w
+        )?;
+        for t in &self.timings {
+            writeln!(
+                f,
+                "{:w$} {:>8} {:>6} {:>6} {:>6} {:>6} {:>6}",
+                t.step,
+                t.duration,
+                t.earliest_start,
+                t.earliest_finish,
+                t.latest_start,
+                t.latest_finish,
+                t.slack(),
+                w = w
+            )?;
+        }
+        writeln!(f, "Critical path: {}", self.critical.join(" -> "))
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn parallel_topo(g: &Graph, worker: usize, durations: &Durations) -> Result<Schedule, StarError> {
+    let indexed = Indexed::new(g);
+    let mut indegree = indexed.indegree.clone();
+    let mut ready = indexed.ready();
     let mut q = BinaryHeap::new();
-    let mut todo = v.to_owned();
+    let mut free = (0..worker).collect::<BTreeSet<_>>();
     let mut t = 0;
-    let mut done = Vec::new();
-
-    while !todo.is_empty() || !q.is_empty() {
-        while q.len() < worker {
-            match next_work(&todo, &done) {
-                Some(i) => {
-                    let node = todo.swap_remove(i);
-                    q.push(Work::new(node, t));
+    let mut slots = Vec::new();
+
+    while slots.len() < indexed.names.len() {
+        while let Some(&w) = free.iter().next() {
+            match ready.pop() {
+                Some(Reverse(i)) => {
+                    free.remove(&w);
+                    q.push(Work::new(i, indexed.names[i], w, t, durations));
                 }
                 None => break,
             }
         }
         if let Some(d) = q.pop() {
+            // Everything finishing at the same time is done before new work is handed out.
             t = d.finish_in();
-            done.push(d.node);
+            let mut finished = vec![d];
+            while q.peek().is_some_and(|w| w.finish_in() == t) {
+                finished.push(q.pop().unwrap());
+            }
+            for d in finished {
+                free.insert(d.worker);
+                slots.push(d.slot(&indexed.names));
+                indexed.finish(d.step, &mut indegree, &mut ready);
+            }
         } else {
-            panic!("Loop?");
+            return Err(g.validate().expect_err("Stuck without a cycle"));
         }
     }
 
-    (done, t)
+    Ok(Schedule {
+        workers: worker,
+        slots,
+        time: t,
+    })
+}
+
+/// The graph, worker count and durations given on the command line.
+fn setup(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    opts: &super::Options,
+) -> Result<(Graph, usize, Durations), StarError> {
+    let g = Graph::build(lines, Format::from_options(opts)?)?;
+    let workers = opts.value("workers", 5)?;
+    if workers == 0 {
+        return Err("At least one worker is needed".into());
+    }
+    let base = opts.value("base", 60)?;
+    let durations = match opts.get("durations") {
+        Some(file) => Durations::parse(solve(file)?, base)?,
+        None => Durations::new(base),
+    };
+    Ok((g, workers, durations))
+}
+
+/// Uses `--workers=<n>` workers (5 by default) and steps taking `--base=<t>` (60 by default)
+/// plus their position in the alphabet, or as long as given in `--durations=<file>`.
+pub fn star2(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    opts: &super::Options,
+) -> super::StarResult {
+    let (g, workers, durations) = setup(lines, opts)?;
+    let s = parallel_topo(&g, workers, &durations)?;
+    println!("Final order: {} finished in {}", s.order(), s.time);
+    Ok(())
+}
+
+/// Prints the schedule of star 2 as given by `--schedule=table|csv|svg`.
+pub fn schedule(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    opts: &super::Options,
+) -> super::StarResult {
+    let (g, workers, durations) = setup(lines, opts)?;
+    let s = parallel_topo(&g, workers, &durations)?;
+    match opts.get("schedule") {
+        Some("table") | Some("") => print!("{}", s.table(&g)),
+        Some("csv") => print!("{}", s.csv()),
+        Some("svg") => print!("{}", s.svg()),
+        Some(f) => return Err(format!("Unknown schedule format \"{}\"", f).into()),
+        None => return Err("No schedule format requested".into()),
+    }
+    Ok(())
+}
+
+/// Prints the critical path analysis and compares the time needed with unlimited workers to
+/// the one with the given number of workers.
+pub fn critical(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    opts: &super::Options,
+) -> super::StarResult {
+    let (g, workers, durations) = setup(lines, opts)?;
+    let analysis = Analysis::new(&g, &durations)?;
+    let s = parallel_topo(&g, workers, &durations)?;
+    print!("{}", analysis);
+    println!("Unlimited workers finish in {}", analysis.length);
+    println!("{} workers finish in {}", workers, s.time);
+    Ok(())
 }
 
-pub fn star2(lines: impl Iterator<Item = std::io::Result<String>>) -> super::StarResult {
-    let g = Graph::build(lines)?;
-    let v: Vec<Node> = g.edges.values().cloned().collect();
-    let (v, t) = parallel_topo(&v, 5);
-    println!(
-        "Final order: {} finished in {}",
-        v.iter().map(|n| n.name).collect::<String>(),
-        t
-    );
+/// Prints the graph in DOT format, with `--dot=annotated` including the schedule of star 2.
+pub fn dot(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    opts: &super::Options,
+) -> super::StarResult {
+    let (g, workers, durations) = setup(lines, opts)?;
+    match opts.get("dot") {
+        Some("") | None => print!("{}", g.dot(None)?),
+        Some("annotated") => {
+            let s = parallel_topo(&g, workers, &durations)?;
+            print!("{}", g.dot(Some((&durations, &s)))?);
+        }
+        Some(d) => return Err(format!("Unknown DOT variant \"{}\"", d).into()),
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn graph(edges: &[(&str, &str)]) -> Graph {
+        let mut g = Graph::new();
+        for &(dep, node) in edges {
+            g.add(dep, node);
+        }
+        g
+    }
+
+    #[test]
+    fn example() {
+        let g = Graph::build(solve("src/day07/test").unwrap(), None).unwrap();
+        assert!(g.is_acyclic());
+        let order = toposort(&g).unwrap();
+        assert_eq!(join_names(order.into_iter()), "CABDFE");
+    }
+
+    #[test]
+    fn cycles() {
+        let g = graph(&[
+            ("A", "B"),
+            ("B", "C"),
+            ("C", "A"),
+            ("C", "D"),
+            ("E", "F"),
+            ("G", "G"),
+        ]);
+        assert!(!g.is_acyclic());
+        assert_eq!(
+            g.components(),
+            vec![
+                vec!["A", "B", "C"],
+                vec!["D"],
+                vec!["E"],
+                vec!["F"],
+                vec!["G"]
+            ]
+        );
+        assert_eq!(g.cycle(), Some(vec!["A", "B", "C"]));
+        assert_eq!(g.unsatisfiable(), vec!["A", "B", "C", "D", "G"]);
+
+        let err = g.validate().unwrap_err().to_string();
+        assert!(err.contains("A -> B -> C -> A"), "{}", err);
+        assert!(err.contains("A, B, C, D, G"), "{}", err);
+        assert!(toposort(&g).is_err());
+        assert!(parallel_topo(&g, 2, &Durations::new(0)).is_err());
+
+        assert_eq!(graph(&[("G", "G")]).cycle(), Some(vec!["G"]));
+    }
+
+    #[test]
+    fn workers() {
+        let g = Graph::build(solve("src/day07/test").unwrap(), None).unwrap();
+        let s = parallel_topo(&g, 2, &Durations::new(0)).unwrap();
+        assert_eq!(s.order(), "CABFDE");
+        assert_eq!(s.time, 15);
+
+        let s = parallel_topo(&g, 5, &Durations::new(60)).unwrap();
+        assert_eq!(s.time, 63 + 61 + 64 + 65);
+    }
+
+    #[test]
+    fn durations() {
+        let lines = ["A 5", "", "E 10"].iter().map(|l| Ok(l.to_string()));
+        let durations = Durations::parse(lines, 0).unwrap();
+        assert_eq!(durations.of("A"), 5);
+        assert_eq!(durations.of("C"), 3);
+
+        let g = Graph::build(solve("src/day07/test").unwrap(), None).unwrap();
+        let s = parallel_topo(&g, 2, &durations).unwrap();
+        assert_eq!(s.order(), "CAFBDE");
+        assert_eq!(s.time, 3 + 6 + 4 + 10);
+
+        let bad = ["A five"].iter().map(|l| Ok(l.to_string()));
+        assert!(Durations::parse(bad, 0).is_err());
+    }
+
+    #[test]
+    fn schedule() {
+        let g = Graph::build(solve("src/day07/test").unwrap(), None).unwrap();
+        let s = parallel_topo(&g, 2, &Durations::new(0)).unwrap();
+        assert_eq!(
+            s.slots,
+            vec![
+                Slot {
+                    worker: 0,
+                    step: "C".to_string(),
+                    start: 0,
+                    end: 3
+                },
+                Slot {
+                    worker: 0,
+                    step: "A".to_string(),
+                    start: 3,
+                    end: 4
+                },
+                Slot {
+                    worker: 0,
+                    step: "B".to_string(),
+                    start: 4,
+                    end: 6
+                },
+                Slot {
+                    worker: 1,
+                    step: "F".to_string(),
+                    start: 3,
+                    end: 9
+                },
+                Slot {
+                    worker: 0,
+                    step: "D".to_string(),
+                    start: 6,
+                    end: 10
+                },
+                Slot {
+                    worker: 0,
+                    step: "E".to_string(),
+                    start: 10,
+                    end: 15
+                },
+            ]
+        );
+        assert_eq!(s.idle(), vec![(1, 0, 3), (1, 9, 15)]);
+        assert_eq!(s.ready(&g, 3), "");
+        assert_eq!(s.ready(&g, 4), "D");
+        assert_eq!(s.ready(&g, 6), "");
+
+        let table = s.table(&g);
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 17);
+        assert_eq!(lines[0], "Second   Worker 1   Worker 2   Done     Ready");
+        assert_eq!(lines[1], "     0      C          .");
+        assert_eq!(lines[5], "     4      B          F       CA       D");
+        assert_eq!(lines[16], "    15      .          .       CABFDE");
+
+        let csv = s.csv();
+        assert!(csv.starts_with("kind,worker,step,start,end\nwork,1,C,0,3\n"));
+        assert!(csv.ends_with("idle,2,,0,3\nidle,2,,9,15\n"));
+
+        let svg = s.svg();
+        assert!(svg.starts_with("<svg "));
+        assert_eq!(svg.matches("<rect ").count(), 8);
+        assert!(svg.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn ready_queue() {
+        // With one worker, B and C wait for it once A is done.
+        let g = graph(&[("A", "B"), ("A", "C")]);
+        let s = parallel_topo(&g, 1, &Durations::new(0)).unwrap();
+        assert_eq!(s.order(), "ABC");
+        assert_eq!(s.ready(&g, 0), "");
+        assert_eq!(s.ready(&g, 1), "C");
+        assert_eq!(s.ready(&g, 3), "");
+    }
+
+    fn lines(s: &str) -> impl Iterator<Item = io::Result<String>> + '_ {
+        s.lines().map(|l| Ok(l.to_string()))
+    }
+
+    fn order(s: &str, format: Option<Format>) -> String {
+        let g = Graph::build(lines(s), format).unwrap();
+        join_names(toposort(&g).unwrap().into_iter())
+    }
+
+    #[test]
+    fn formats() {
+        let edges = "C -> A -> B -> E\nA -> D -> E\nC -> F -> E\n";
+        let make = "# Example\nA: C\nB: A\nD: A\nE: B D F\n\techo E\nF: C\nX = 1\n";
+        let assigned = "CC = gcc\nLD := ld\nA: C\nB: A\nD: A\nE: B D F\nF: C\n";
+        let json =
+            r#"{"C": ["A", "F"], "A": ["B", "D"], "B": ["E"], "D": ["E"], "F": ["E"], "E": []}"#;
+        for (s, format) in &[
+            (edges, Format::Edges),
+            (make, Format::Make),
+            (assigned, Format::Make),
+            (json, Format::Json),
+        ] {
+            assert_eq!(
+                Format::detect(&s.lines().map(String::from).collect::<Vec<_>>()),
+                *format
+            );
+            assert_eq!(order(s, None), "CABDFE");
+            assert_eq!(order(s, Some(*format)), "CABDFE");
+        }
+
+        assert!(Graph::parse(lines("A -> -> B"), None).is_err());
+        assert!(Graph::parse(lines("Step A must be done"), Some(Format::Aoc)).is_err());
+        assert!(Graph::parse(lines("{\"A\": [\"B\",]}"), None).is_err());
+        assert!(Graph::parse(lines("{\"A\": []} x"), None).is_err());
+        assert_eq!(order("{\"\\u0041\\\"\": [\"B\"]}", None), "A\",B");
+    }
+
+    #[test]
+    fn make_and_json_details() {
+        // Special targets aren't steps, and '=' only matters in assignments.
+        let make = ".PHONY: all\nall: build\nbuild: src\n\tcc -o build src\nCC ?= gcc\n";
+        assert_eq!(order(make, None), "src,build,all");
+        let json = r#"{"x=1": ["y"]}"#;
+        assert_eq!(order(json, None), "x=1,y");
+
+        let escapes = r#"{"\b\f\r\uD83D\uDE00": ["x"]}"#;
+        let g = Graph::build(lines(escapes), None).unwrap();
+        assert!(g.edges.contains_key("\u{8}\u{c}\r\u{1F600}"));
+        for bad in &[
+            r#"{"\uD83D": []}"#,
+            r#"{"\uDE00": []}"#,
+            r#"{"\uD83Dx": []}"#,
+        ] {
+            let err = Graph::parse(lines(bad), None).unwrap_err();
+            assert!(err.to_string().contains("surrogate"), "{}", err);
+        }
+        assert!(Graph::parse(lines(r#"{"\u+041": []}"#), None).is_err());
+    }
+
+    #[test]
+    fn names() {
+        let s = "build -> test -> release\nfetch -> build\nconfigure -> build\ndocs\n";
+        assert_eq!(order(s, None), "configure,docs,fetch,build,test,release");
+
+        let g = Graph::build(lines(s), None).unwrap();
+        let durations = Durations::parse(lines("test 10"), 2).unwrap();
+        assert_eq!(durations.of("build"), 3);
+        assert_eq!(durations.of("B"), 4);
+        let s = parallel_topo(&g, 2, &durations).unwrap();
+        assert_eq!(s.order(), "configure,docs,fetch,build,test,release");
+        assert_eq!(s.time, 3 + 3 + 3 + 10 + 3);
+
+        let err = Graph::build(lines("setup -> run -> setup"), None).unwrap_err();
+        assert!(err.to_string().contains("run -> setup -> run"), "{}", err);
+    }
+
+    #[test]
+    fn large() {
+        // Every step depends on up to three earlier ones, picked by a small LCG.
+        let n = 30_000;
+        let mut g = Graph::new();
+        let mut x: u64 = 7;
+        for i in 0..n {
+            let name = format!("s{:05}", (i * 7919) % n);
+            g.add_step(&name);
+            for _ in 0..3 {
+                x = x.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                if i > 0 {
+                    let dep = (x >> 33) as usize % i;
+                    g.add(&format!("s{:05}", (dep * 7919) % n), &name);
+                }
+            }
+        }
+        assert!(g.is_acyclic());
+
+        let order = toposort(&g).unwrap();
+        assert_eq!(order.len(), n);
+        let position: HashMap<&str, usize> =
+            order.iter().enumerate().map(|(i, &s)| (s, i)).collect();
+        for node in g.edges.values() {
+            assert!(node
+                .dep
+                .iter()
+                .all(|d| position[d.as_str()] < position[node.name.as_str()]));
+        }
+
+        let s = parallel_topo(&g, 8, &Durations::new(0)).unwrap();
+        assert_eq!(s.slots.len(), n);
+        let end: HashMap<&str, usize> = s.slots.iter().map(|s| (s.step.as_str(), s.end)).collect();
+        for slot in &s.slots {
+            assert!(g.edges[&slot.step]
+                .dep
+                .iter()
+                .all(|d| end[d.as_str()] <= slot.start));
+        }
+    }
+
+    #[test]
+    fn critical_path() {
+        let g = Graph::build(solve("src/day07/test").unwrap(), None).unwrap();
+        let a = Analysis::new(&g, &Durations::new(0)).unwrap();
+        assert_eq!(a.length, 14);
+        assert_eq!(a.critical, vec!["C", "F", "E"]);
+        let slack = a
+            .timings
+            .iter()
+            .map(|t| (t.step.as_str(), t.slack()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            slack,
+            vec![("C", 0), ("A", 1), ("B", 3), ("D", 1), ("F", 0), ("E", 0)]
+        );
+        assert_eq!(
+            a.timings[3],
+            Timing {
+                step: "D".to_string(),
+                duration: 4,
+                earliest_start: 4,
+                earliest_finish: 8,
+                latest_start: 5,
+                latest_finish: 9,
+            }
+        );
+
+        // Two workers can't keep up with the critical path, enough workers can.
+        assert_eq!(parallel_topo(&g, 2, &Durations::new(0)).unwrap().time, 15);
+        assert_eq!(parallel_topo(&g, 6, &Durations::new(0)).unwrap().time, 14);
+
+        let shown = a.to_string();
+        assert!(shown.starts_with("Step Duration     ES     EF     LS     LF  Slack\n"));
+        assert!(shown.ends_with("Critical path: C -> F -> E\n"));
+    }
+
+    #[test]
+    fn dot() {
+        let g = Graph::build(solve("src/day07/test").unwrap(), None).unwrap();
+        assert_eq!(
+            g.to_string(),
+            "A -> C\nB -> A\nC -> \nD -> A\nE -> B, D, F\nF -> C\n"
+        );
+
+        let dot = g.dot(None).unwrap();
+        let lines = dot.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "digraph steps {");
+        assert_eq!(
+            lines[3..9],
+            [
+                "    \"C\";",
+                "    \"A\";",
+                "    \"B\";",
+                "    \"D\";",
+                "    \"F\";",
+                "    \"E\";"
+            ]
+        );
+        assert_eq!(lines[9], "    \"C\" -> \"A\";");
+        assert_eq!(lines.len(), 17);
+        assert_eq!(lines[16], "}");
+
+        let durations = Durations::new(0);
+        let s = parallel_topo(&g, 2, &durations).unwrap();
+        let dot = g.dot(Some((&durations, &s))).unwrap();
+        assert!(
+            dot.contains("    \"F\" [label=\"F\\n#5, 6s\\nworker 2, 3-9\"];\n"),
+            "{}",
+            dot
+        );
+
+        let g = graph(&[("say \"hi\"", "b\\")]);
+        let dot = g.dot(None).unwrap();
+        assert!(
+            dot.contains("    \"say \\\"hi\\\"\" -> \"b\\\\\";\n"),
+            "{}",
+            dot
+        );
+    }
+}
@@ -1,3 +1,7 @@
+use std::io::{self, BufRead};
+
+use itertools::Itertools;
+
 use super::*;
 
 #[derive(Debug)]
@@ -6,65 +10,329 @@ struct Node<'a> {
     metadata: &'a [u32],
 }
 
+/// A node still missing some of its children while parsing.
+struct Partial<'a> {
+    offset: usize,
+    n_children: usize,
+    n_metadata: usize,
+    children: Vec<Node<'a>>,
+}
+
 impl<'a> Node<'a> {
-    fn build(s: &[u32]) -> Result<(Node, usize), StarError> {
-        if s.len() < 2 {
-            return Err(format!("Node to small: {:?}", s).into());
-        }
-
-        let n_children = s[0] as usize;
-        let n_metadata = s[1] as usize;
-
-        let mut data = &s[2..s.len() - n_metadata];
-
-        if n_children == 0 {
-            let metadata = &s[2..2 + n_metadata];
-            Ok((
-                Node {
-                    children: Vec::new(),
-                    metadata,
-                },
-                2 + n_metadata,
-            ))
-        } else {
-            let mut children = Vec::new();
-            for _ in 0..n_children {
-                let (child, c_size) = Node::build(data)?;
-                children.push(child);
-                data = &data[c_size..];
-            }
-
-            let size = s.len() - data.len();
-            Ok((
-                Node {
-                    children,
-                    metadata: &s[size - n_metadata..size],
-                },
-                s.len() - data.len(),
-            ))
-        }
-    }
-
-    fn sum_metadata(&self) -> u32 {
-        self.children.iter().map(|c| c.sum_metadata()).sum::<u32>()
-            + self.metadata.iter().sum::<u32>()
-    }
-
-    fn get_value(&self) -> u32 {
-        if self.children.is_empty() {
-            self.sum_metadata()
-        } else {
-            let mut value = 0;
-            for &idx in self.metadata.iter() {
-                if idx == 0 {
-                    continue;
+    /// Parses the tree at the start of `s` without recursion, returning it with the number of
+    /// values it takes up. Errors name the offset of the node that doesn't fit the input.
+    fn build(s: &'a [u32]) -> Result<(Node<'a>, usize), StarError> {
+        let mut stack: Vec<Partial> = Vec::new();
+        let mut pos = 0;
+        loop {
+            if s.len() - pos < 2 {
+                return Err(format!("Offset {}: expected a node header, input ends", pos).into());
+            }
+            let n_children = s[pos] as usize;
+            let n_metadata = s[pos + 1] as usize;
+            // Every child takes at least its header.
+            let needed = n_children.saturating_mul(2).saturating_add(n_metadata);
+            if needed > s.len() - pos - 2 {
+                return Err(format!(
+                    "Offset {}: node with {} children and {} metadata entries needs at least {} \
+                     more values, only {} left",
+                    pos,
+                    n_children,
+                    n_metadata,
+                    needed,
+                    s.len() - pos - 2
+                )
+                .into());
+            }
+            stack.push(Partial {
+                offset: pos,
+                n_children,
+                n_metadata,
+                children: Vec::with_capacity(n_children),
+            });
+            pos += 2;
+
+            // Close every node that has all its children.
+            while stack
+                .last()
+                .is_some_and(|p| p.children.len() == p.n_children)
+            {
+                let p = stack.pop().unwrap();
+                if p.n_metadata > s.len() - pos {
+                    return Err(format!(
+                        "Offset {}: node from offset {} needs {} metadata entries, only {} left",
+                        pos,
+                        p.offset,
+                        p.n_metadata,
+                        s.len() - pos
+                    )
+                    .into());
+                }
+                let node = Node {
+                    children: p.children,
+                    metadata: &s[pos..pos + p.n_metadata],
+                };
+                pos += p.n_metadata;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => return Ok((node, pos)),
+                }
+            }
+        }
+    }
+
+    /// Parses a tree that has to take up all of `s`.
+    fn parse(s: &'a [u32]) -> Result<Node<'a>, StarError> {
+        let (tree, size) = Node::build(s)?;
+        if size < s.len() {
+            return Err(format!(
+                "Offset {}: {} values left after the end of the tree",
+                size,
+                s.len() - size
+            )
+            .into());
+        }
+        Ok(tree)
+    }
+
+    /// The tree in the input format, the inverse of `build`.
+    fn serialize(&self) -> Vec<u32> {
+        let mut ret = Vec::new();
+        let mut todo = vec![(self, 0)];
+        while let Some((n, child)) = todo.pop() {
+            if child == 0 {
+                ret.push(n.children.len() as u32);
+                ret.push(n.metadata.len() as u32);
+            }
+            match n.children.get(child) {
+                Some(c) => {
+                    todo.push((n, child + 1));
+                    todo.push((c, 0));
                 }
+                None => ret.extend(n.metadata),
+            }
+        }
+        ret
+    }
+
+    /// The metadata sum and the value of every subtree, in pre-order. Every node is evaluated
+    /// once, no matter how often its parent references it. Values can double with every level,
+    /// so they can outgrow 64 bits long before the tree gets large.
+    fn totals(&self) -> Result<Vec<(u64, u64)>, StarError> {
+        let mut ret = Vec::new();
+        // Every frame is a node, its index in pre-order and the totals of its children so far.
+        let mut todo = vec![(self, 0, Vec::new())];
+        ret.push((0, 0));
+        while let Some((n, id, totals)) = todo.last_mut() {
+            if let Some(c) = n.children.get(totals.len()) {
+                let c_id = ret.len();
+                ret.push((0, 0));
+                totals.push((0, 0));
+                todo.push((c, c_id, Vec::new()));
+                continue;
+            }
+
+            let too_large = || format!("Node {}: metadata sum or value too large", id);
+            let sum = n
+                .metadata
+                .iter()
+                .map(|&m| m as u64)
+                .chain(totals.iter().map(|t| t.0))
+                .try_fold(0u64, u64::checked_add)
+                .ok_or_else(too_large)?;
+            let value = if n.children.is_empty() {
+                sum
+            } else {
+                n.metadata
+                    .iter()
+                    .filter_map(|&i| totals.get((i as usize).wrapping_sub(1)))
+                    .map(|t| t.1)
+                    .try_fold(0u64, u64::checked_add)
+                    .ok_or_else(too_large)?
+            };
+            ret[*id] = (sum, value);
+            todo.pop();
+            if let Some((_, _, parent)) = todo.last_mut() {
+                *parent.last_mut().unwrap() = (sum, value);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// An indented line per node with its metadata, metadata sum and value.
+    fn pretty(&self) -> Result<String, StarError> {
+        let totals = self.totals()?;
+        let mut ret = String::new();
+        let mut todo = vec![(self, 0)];
+        for (sum, value) in totals {
+            let (n, depth) = todo.pop().unwrap();
+            ret += &format!(
+                "{}- metadata [{}], sum {}, value {}\n",
+                "  ".repeat(depth),
+                n.metadata.iter().join(", "),
+                sum,
+                value
+            );
+            todo.extend(n.children.iter().rev().map(|c| (c, depth + 1)));
+        }
+        Ok(ret)
+    }
+
+    /// The tree as nested JSON objects with `children` and `metadata` arrays.
+    fn json(&self) -> String {
+        let mut ret = String::new();
+        // Nodes to open, with whether they are the first child, and nodes to close.
+        let mut todo = vec![(self, Some(true))];
+        while let Some((n, open)) = todo.pop() {
+            match open {
+                Some(first) => {
+                    if !first {
+                        ret += ",";
+                    }
+                    ret += "{\"children\":[";
+                    todo.push((n, None));
+                    todo.extend(
+                        n.children
+                            .iter()
+                            .enumerate()
+                            .rev()
+                            .map(|(i, c)| (c, Some(i == 0))),
+                    );
+                }
+                None => ret += &format!("],\"metadata\":[{}]}}", n.metadata.iter().join(",")),
+            }
+        }
+        ret
+    }
+}
 
-                if let Some(child) = self.children.get(idx as usize - 1) {
-                    value += child.get_value();
+/// A node reached in pre-order, with where it hangs in the tree.
+#[derive(Debug)]
+struct Visit<'t, 'a> {
+    node: &'t Node<'a>,
+    depth: usize,
+    parent: Option<usize>,
+    child: usize,
+}
+
+/// A metadata entry of a node with children that doesn't refer to any of them.
+#[derive(Debug, PartialEq, Eq)]
+struct Dangling {
+    node: usize,
+    entry: usize,
+    reference: u32,
+}
+
+/// Questions about a tree. Nodes are numbered in pre-order, the root being 0, and every node's
+/// metadata sum and value is computed once up front.
+struct Query<'t, 'a> {
+    visits: Vec<Visit<'t, 'a>>,
+    totals: Vec<(u64, u64)>,
+}
+
+impl<'t, 'a> Query<'t, 'a> {
+    fn new(tree: &'t Node<'a>) -> Result<Query<'t, 'a>, StarError> {
+        let mut visits = Vec::new();
+        let mut todo = vec![(tree, 0, None, 0)];
+        while let Some((node, depth, parent, child)) = todo.pop() {
+            let id = visits.len();
+            visits.push(Visit {
+                node,
+                depth,
+                parent,
+                child,
+            });
+            todo.extend(
+                node.children
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .map(|(i, c)| (c, depth + 1, Some(id), i + 1)),
+            );
+        }
+        Ok(Query {
+            visits,
+            totals: tree.totals()?,
+        })
+    }
+
+    fn count(&self) -> usize {
+        self.visits.len()
+    }
+
+    /// The depth of the deepest node, the root being at depth 0.
+    fn max_depth(&self) -> usize {
+        self.visits.iter().map(|v| v.depth).max().unwrap_or(0)
+    }
+
+    fn value(&self, node: usize) -> u64 {
+        self.totals[node].1
+    }
+
+    /// The child numbers leading from the root to a node, counting from 1 like the metadata.
+    fn path(&self, node: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        let mut current = node;
+        while let Some(parent) = self.visits[current].parent {
+            path.push(self.visits[current].child);
+            current = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// The node at the end of a path of child numbers.
+    fn find(&self, path: &[usize]) -> Option<usize> {
+        let mut node = 0;
+        for &child in path {
+            node = (node + 1..self.visits.len())
+                .take_while(|&i| self.visits[i].depth > self.visits[node].depth)
+                .find(|&i| self.visits[i].parent == Some(node) && self.visits[i].child == child)?;
+        }
+        Some(node)
+    }
+
+    /// The first node in pre-order with the highest value.
+    fn highest_value(&self) -> usize {
+        (0..self.count())
+            .rev()
+            .max_by_key(|&i| self.value(i))
+            .unwrap_or(0)
+    }
+
+    fn dangling(&self) -> Vec<Dangling> {
+        let mut ret = Vec::new();
+        for (id, v) in self.visits.iter().enumerate() {
+            if v.node.children.is_empty() {
+                continue;
+            }
+            for (entry, &reference) in v.node.metadata.iter().enumerate() {
+                if reference == 0 || reference as usize > v.node.children.len() {
+                    ret.push(Dangling {
+                        node: id,
+                        entry,
+                        reference,
+                    });
                 }
             }
-            value
+        }
+        ret
+    }
+
+    fn show_path(&self, node: usize) -> String {
+        match self.path(node) {
+            ref p if p.is_empty() => "root".to_string(),
+            p => p.iter().join("."),
+        }
+    }
+}
+
+/// Takes the children apart without recursion, so deep trees don't overflow the stack.
+impl<'a> Drop for Node<'a> {
+    fn drop(&mut self) {
+        let mut todo = std::mem::take(&mut self.children);
+        while let Some(mut n) = todo.pop() {
+            todo.append(&mut n.children);
         }
     }
 }
@@ -80,16 +348,439 @@ fn parse_input(
         .collect::<Result<Vec<_>, _>>()
 }
 
-pub fn star1(lines: impl Iterator<Item = std::io::Result<String>>) -> StarResult {
+/// Prints the tree as given by `--print=tree|json|flat`.
+pub fn print(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    opts: &super::Options,
+) -> StarResult {
     let input = parse_input(lines)?;
-    let (tree, _) = Node::build(&input)?;
-    println!("Sum of metadata: {}", tree.sum_metadata());
+    let tree = Node::parse(&input)?;
+    match opts.get("print") {
+        Some("tree") | Some("") => print!("{}", tree.pretty()?),
+        Some("json") => println!("{}", tree.json()),
+        Some("flat") => println!("{}", tree.serialize().iter().join(" ")),
+        Some(p) => return Err(format!("Unknown output format \"{}\"", p).into()),
+        None => return Err("No output format requested".into()),
+    }
     Ok(())
 }
 
-pub fn star2(lines: impl Iterator<Item = std::io::Result<String>>) -> StarResult {
+/// The whitespace separated numbers read from a byte stream, scanning the reader's buffer
+/// directly.
+struct Numbers<R> {
+    r: R,
+    offset: usize,
+}
+
+impl<R: BufRead> Numbers<R> {
+    fn new(r: R) -> Numbers<R> {
+        Numbers { r, offset: 0 }
+    }
+}
+
+impl<R: BufRead> Iterator for Numbers<R> {
+    type Item = Result<u32, StarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut number: Option<u32> = None;
+        loop {
+            let buf = match self.r.fill_buf() {
+                Ok(buf) => buf,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if buf.is_empty() {
+                return number.map(Ok);
+            }
+            let mut used = buf.len();
+            let mut done = false;
+            let mut error = None;
+            for (i, &b) in buf.iter().enumerate() {
+                let offset = self.offset + i;
+                match b {
+                    b'0'..=b'9' => {
+                        let n = number
+                            .unwrap_or(0)
+                            .checked_mul(10)
+                            .and_then(|n| n.checked_add((b - b'0') as u32));
+                        match n {
+                            Some(n) => number = Some(n),
+                            None => {
+                                error = Some(format!("Byte {}: number too large", offset));
+                                used = i + 1;
+                                break;
+                            }
+                        }
+                    }
+                    _ if b.is_ascii_whitespace() => {
+                        if number.is_some() {
+                            used = i + 1;
+                            done = true;
+                            break;
+                        }
+                    }
+                    _ => {
+                        error = Some(format!("Byte {}: unexpected {:?}", offset, b as char));
+                        used = i + 1;
+                        break;
+                    }
+                }
+            }
+            self.r.consume(used);
+            self.offset += used;
+            if let Some(e) = error {
+                return Some(Err(e.into()));
+            }
+            if done {
+                return number.map(Ok);
+            }
+        }
+    }
+}
+
+/// A node being read by `evaluate`.
+struct Pending {
+    offset: usize,
+    n_children: usize,
+    n_metadata: usize,
+    children: Vec<(u64, u64)>,
+}
+
+/// Reads a tree number by number and returns its metadata sum and the value of the root. Only
+/// the nodes on the path to the current one are kept, with the sums and values of their
+/// children so far.
+fn evaluate(r: impl BufRead) -> Result<(u64, u64), StarError> {
+    let mut numbers = Numbers::new(r).enumerate();
+    // Reads the next number, of the metadata of the node at `node` or of a header if `None`.
+    let mut next = |node: Option<usize>| match numbers.next() {
+        Some((i, n)) => n.map(|n| (i, n)),
+        None => Err(StarError::from(match node {
+            Some(offset) => format!(
+                "Input ends, expected metadata of the node from offset {}",
+                offset
+            ),
+            None => "Input ends, expected a node header".to_string(),
+        })),
+    };
+
+    let mut stack: Vec<Pending> = Vec::new();
+    loop {
+        let (offset, n_children) = next(None)?;
+        let (_, n_metadata) = next(None)?;
+        stack.push(Pending {
+            offset,
+            n_children: n_children as usize,
+            n_metadata: n_metadata as usize,
+            children: Vec::new(),
+        });
+
+        while stack
+            .last()
+            .is_some_and(|p| p.children.len() == p.n_children)
+        {
+            let p = stack.pop().unwrap();
+            let too_large = || format!("Offset {}: metadata sum or value too large", p.offset);
+            let mut sum = p
+                .children
+                .iter()
+                .try_fold(0u64, |s, c| s.checked_add(c.0))
+                .ok_or_else(too_large)?;
+            let mut value = 0u64;
+            for _ in 0..p.n_metadata {
+                let (_, m) = next(Some(p.offset))?;
+                sum = sum.checked_add(m as u64).ok_or_else(too_large)?;
+                if let Some(c) = p.children.get((m as usize).wrapping_sub(1)) {
+                    value = value.checked_add(c.1).ok_or_else(too_large)?;
+                }
+            }
+            if p.children.is_empty() {
+                value = sum;
+            }
+            match stack.last_mut() {
+                Some(parent) => parent.children.push((sum, value)),
+                None => {
+                    return match numbers.next() {
+                        None => Ok((sum, value)),
+                        Some((i, _)) => Err(format!(
+                            "Offset {}: values left after the end of the tree",
+                            i
+                        )
+                        .into()),
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Prints statistics about the tree and the metadata entries not referring to any child. With
+/// `--query=<path>`, like `2.1` or `root`, prints the node at the end of the path instead.
+pub fn query(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    opts: &super::Options,
+) -> StarResult {
     let input = parse_input(lines)?;
-    let (tree, _) = Node::build(&input)?;
-    println!("Value of root: {}", tree.get_value());
+    let tree = Node::parse(&input)?;
+    let q = Query::new(&tree)?;
+
+    match opts.get("query") {
+        None | Some("") => (),
+        Some(p) => {
+            let path = match p {
+                "root" => Vec::new(),
+                _ => p
+                    .split('.')
+                    .map(|c| c.parse::<usize>().map_err(StarError::from))
+                    .collect::<Result<Vec<_>, _>>()?,
+            };
+            let id = q.find(&path).ok_or_else(|| format!("No node at {}", p))?;
+            let (sum, value) = q.totals[id];
+            let node = q.visits[id].node;
+            println!(
+                "Node {}: {} children, metadata [{}], sum {}, value {}",
+                q.show_path(id),
+                node.children.len(),
+                node.metadata.iter().join(", "),
+                sum,
+                value
+            );
+            return Ok(());
+        }
+    }
+
+    println!("Nodes: {}", q.count());
+    println!("Maximum depth: {}", q.max_depth());
+    let best = q.highest_value();
+    println!("Highest value: {} at {}", q.value(best), q.show_path(best));
+    let dangling = q.dangling();
+    println!("Dangling references: {}", dangling.len());
+    for d in dangling {
+        println!(
+            "  {} entry {}: no child {}",
+            q.show_path(d.node),
+            d.entry + 1,
+            d.reference
+        );
+    }
+    Ok(())
+}
+
+pub fn star1(input: impl BufRead) -> StarResult {
+    let (sum, _) = evaluate(input)?;
+    println!("Sum of metadata: {}", sum);
+    Ok(())
+}
+
+pub fn star2(input: impl BufRead) -> StarResult {
+    let (_, value) = evaluate(input)?;
+    println!("Value of root: {}", value);
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn example() {
+        let input = parse_input(solve("src/day08/test").unwrap()).unwrap();
+        let tree = Node::parse(&input).unwrap();
+        assert_eq!(tree.totals().unwrap()[0], (138, 66));
+    }
+
+    #[test]
+    fn deep() {
+        // A chain of nodes with one child and one metadata entry each.
+        let depth = 200_000;
+        let mut input = Vec::new();
+        for _ in 0..depth {
+            input.extend(&[1, 1]);
+        }
+        input.extend(&[0, 1]);
+        input.resize(input.len() + depth + 1, 2);
+        let tree = Node::parse(&input).unwrap();
+        assert_eq!(tree.totals().unwrap()[0], (2 * (depth as u64 + 1), 0));
+    }
+
+    #[test]
+    fn errors() {
+        let err = |s: &[u32]| match Node::parse(s) {
+            Err(StarError::StringError(e)) => e,
+            r => panic!("Expected an error, got {:?}", r),
+        };
+        assert!(err(&[]).starts_with("Offset 0: expected a node header"));
+        assert!(err(&[2, 3, 0, 3, 10, 11, 12]).starts_with("Offset 0: node with 2 children"));
+        assert!(err(&[1, 1, 0, 3, 1, 2, 3]).starts_with("Offset 7: node from offset 0 needs 1"));
+        assert!(err(&[1, 1, 1, 0, 0, 3, 1]).starts_with("Offset 4: node with 0 children and 3"));
+        assert_eq!(
+            err(&[0, 1, 5, 7, 7]),
+            "Offset 3: 2 values left after the end of the tree"
+        );
+        assert!(err(&[1, 0, 1, 0]).starts_with("Offset 2: node with 1 children"));
+    }
+
+    #[test]
+    fn output() {
+        let input = parse_input(solve("src/day08/test").unwrap()).unwrap();
+        let tree = Node::parse(&input).unwrap();
+        assert_eq!(tree.serialize(), input);
+        assert_eq!(
+            tree.totals().unwrap(),
+            vec![(138, 66), (33, 33), (101, 0), (99, 99)]
+        );
+        assert_eq!(
+            tree.pretty().unwrap(),
+            "- metadata [1, 1, 2], sum 138, value 66\n\
+             \x20 - metadata [10, 11, 12], sum 33, value 33\n\
+             \x20 - metadata [2], sum 101, value 0\n\
+             \x20   - metadata [99], sum 99, value 99\n"
+        );
+        assert_eq!(
+            tree.json(),
+            "{\"children\":[\
+             {\"children\":[],\"metadata\":[10,11,12]},\
+             {\"children\":[{\"children\":[],\"metadata\":[99]}],\"metadata\":[2]}\
+             ],\"metadata\":[1,1,2]}"
+        );
+
+        let input = [3, 1, 0, 0, 1, 0, 0, 0, 0, 1, 4, 7];
+        let tree = Node::parse(&input).unwrap();
+        assert_eq!(tree.serialize(), input);
+        assert_eq!(
+            tree.totals().unwrap(),
+            vec![(11, 0), (0, 0), (0, 0), (0, 0), (4, 4)]
+        );
+    }
+
+    /// A chain of `depth` nodes whose metadata refers to their only child twice, so the value
+    /// doubles with every level.
+    fn doubling(depth: usize) -> Vec<u32> {
+        let mut input = Vec::new();
+        for _ in 0..depth {
+            input.extend(&[1, 2]);
+        }
+        input.extend(&[0, 1, 1]);
+        for _ in 0..depth {
+            input.extend(&[1, 1]);
+        }
+        input
+    }
+
+    #[test]
+    fn overflow() {
+        let input = doubling(63);
+        let tree = Node::parse(&input).unwrap();
+        assert_eq!(tree.totals().unwrap()[0], (1 + 2 * 63, 1 << 63));
+        let text = input.iter().join(" ");
+        assert_eq!(evaluate(text.as_bytes()).unwrap(), (1 + 2 * 63, 1 << 63));
+
+        let input = doubling(64);
+        let tree = Node::parse(&input).unwrap();
+        match tree.totals() {
+            Err(StarError::StringError(e)) => {
+                assert_eq!(e, "Node 0: metadata sum or value too large")
+            }
+            r => panic!("Expected an error, got {:?}", r),
+        }
+        assert!(Query::new(&tree).is_err());
+        assert!(tree.pretty().is_err());
+
+        // The streaming evaluator names the offset of the node.
+        let mut input = vec![1, 1];
+        input.extend(doubling(64));
+        input.push(1);
+        let text = input.iter().join(" ");
+        match evaluate(text.as_bytes()) {
+            Err(StarError::StringError(e)) => {
+                assert_eq!(e, "Offset 2: metadata sum or value too large")
+            }
+            r => panic!("Expected an error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn streaming() {
+        let file = File::open("src/day08/test").unwrap();
+        assert_eq!(evaluate(BufReader::new(file)).unwrap(), (138, 66));
+
+        let input = "3 1 0 0 1 0 0 0 0 1 4 7";
+        assert_eq!(evaluate(input.as_bytes()).unwrap(), (11, 0));
+        let input = "1 3\n0 1 4\n1 1 2\n";
+        assert_eq!(evaluate(input.as_bytes()).unwrap(), (8, 8));
+        // Numbers split across buffer refills.
+        for capacity in 1..5 {
+            let r = BufReader::with_capacity(capacity, "10 0 12 345 6789 ".as_bytes());
+            let numbers = Numbers::new(r).collect::<Result<Vec<_>, _>>().unwrap();
+            assert_eq!(numbers, vec![10, 0, 12, 345, 6789]);
+            let r = BufReader::with_capacity(capacity, "0 1 4".as_bytes());
+            assert_eq!(evaluate(r).unwrap(), (4, 4));
+        }
+
+        let depth = 200_000;
+        let input = "1 1 ".repeat(depth) + "0 1" + &" 1".repeat(depth + 1);
+        assert_eq!(evaluate(input.as_bytes()).unwrap(), (depth as u64 + 1, 1));
+
+        let err = |s: &str| match evaluate(s.as_bytes()) {
+            Err(StarError::StringError(e)) => e,
+            r => panic!("Expected an error, got {:?}", r),
+        };
+        assert_eq!(err(""), "Input ends, expected a node header");
+        assert_eq!(err("1 1 0"), "Input ends, expected a node header");
+        assert_eq!(
+            err("1 2 0 0 1"),
+            "Input ends, expected metadata of the node from offset 0"
+        );
+        assert_eq!(
+            err("0 1 1 5"),
+            "Offset 3: values left after the end of the tree"
+        );
+        assert_eq!(err("0 1 x"), "Byte 4: unexpected 'x'");
+        assert_eq!(err("0 1 4294967296"), "Byte 13: number too large");
+    }
+
+    #[test]
+    fn queries() {
+        let input = parse_input(solve("src/day08/test").unwrap()).unwrap();
+        let tree = Node::parse(&input).unwrap();
+        let q = Query::new(&tree).unwrap();
+        assert_eq!(q.count(), 4);
+        assert_eq!(q.max_depth(), 2);
+        assert_eq!(
+            (0..4).map(|i| q.value(i)).collect::<Vec<_>>(),
+            vec![66, 33, 0, 99]
+        );
+        assert_eq!(q.path(3), vec![2, 1]);
+        assert_eq!(q.show_path(0), "root");
+        assert_eq!(q.show_path(3), "2.1");
+        assert_eq!(q.find(&[2, 1]), Some(3));
+        assert_eq!(q.find(&[]), Some(0));
+        assert_eq!(q.find(&[1, 1]), None);
+        assert_eq!(q.find(&[3]), None);
+        assert_eq!(q.highest_value(), 3);
+        assert_eq!(
+            q.dangling(),
+            vec![Dangling {
+                node: 2,
+                entry: 0,
+                reference: 2
+            }]
+        );
+
+        // The shared child is evaluated once per tree, not once per reference.
+        let mut input = Vec::new();
+        for _ in 0..12 {
+            input.extend(&[1, 3]);
+        }
+        input.extend(&[0, 1, 3]);
+        for _ in 0..12 {
+            input.extend(&[1, 1, 0]);
+        }
+        let tree = Node::parse(&input).unwrap();
+        let q = Query::new(&tree).unwrap();
+        assert_eq!(q.count(), 13);
+        assert_eq!(q.max_depth(), 12);
+        assert_eq!(q.value(0), 3 * 2u64.pow(12));
+        assert_eq!(q.dangling().len(), 12);
+        assert_eq!(q.find(&[1; 12]), Some(12));
+    }
+}
@@ -1,93 +1,926 @@
 use super::*;
 use std::collections::VecDeque;
-use std::iter::FromIterator;
-
-fn parse_input(s: std::io::Result<String>) -> Result<(usize, usize), StarError> {
-    let s = s.map_err(StarError::from)?;
-    let v = s
-        .split_whitespace()
-        .take(2)
-        .map(|d| d.parse::<usize>().map_err(|e| e.into()))
-        .collect::<Result<Vec<_>, StarError>>()?;
-
-    if v.len() != 2 {
-        Err(format!("Expected 2 inputs, got \"{:?}\"", v).into())
+use std::io::{BufWriter, Read, Write};
+use std::time::{Duration, Instant};
+
+/// Parses a game, given either as the puzzle sentence "428 players; last marble is worth
+/// 72061 points" or just as "428 72061".
+fn parse_game(s: &str) -> Result<(usize, usize), String> {
+    let w = s.split_whitespace().collect::<Vec<_>>();
+    let (p, n) = match w[..] {
+        [p, n] | [p, "players;", "last", "marble", "is", "worth", n, "points"] => (p, n),
+        _ => return Err(format!("Expected a game, got \"{}\"", s)),
+    };
+    let p = p
+        .parse::<usize>()
+        .map_err(|e| format!("Bad number of players \"{}\": {}", p, e))?;
+    let n = n
+        .parse::<usize>()
+        .map_err(|e| format!("Bad last marble \"{}\": {}", n, e))?;
+    if p == 0 {
+        return Err("A game needs at least one player".to_string());
+    }
+    Ok((p, n))
+}
+
+/// Parses every game, skipping empty lines. Fails with the errors of all bad lines.
+fn parse_input(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+) -> Result<Vec<(usize, usize)>, StarError> {
+    let mut games = Vec::new();
+    let mut errors = Vec::new();
+    for (i, l) in (1..).zip(lines) {
+        let l = l?;
+        if l.trim().is_empty() {
+            continue;
+        }
+        match parse_game(&l) {
+            Ok(g) => games.push(g),
+            Err(e) => errors.push(format!("Line {}: {}", i, e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(games)
     } else {
-        Ok((v[0], v[1]))
+        Err(errors.join("\n").into())
+    }
+}
+
+/// The circle of marbles. Marbles are added and taken away at the current marble, clockwise
+/// being the direction the game moves in.
+trait Circle: Sized {
+    /// A circle with only `marble`, with room for marbles up to `n`.
+    fn with_marble(marble: usize, n: usize) -> Result<Self, StarError>;
+    /// A circle with only marble 0, with room for marbles up to `n`.
+    fn new(n: usize) -> Result<Self, StarError> {
+        Self::with_marble(0, n)
+    }
+    fn rotate_cw(&mut self, n: usize);
+    fn rotate_ccw(&mut self, n: usize);
+    /// Puts a marble counter-clockwise of the current one and makes it the current marble.
+    fn insert(&mut self, marble: usize);
+    /// Takes away the current marble, the one clockwise of it becoming the current marble.
+    fn remove(&mut self) -> usize;
+    /// Puts a marble counter-clockwise of the current one, making it the last one clockwise.
+    fn push(&mut self, marble: usize);
+    /// The marbles clockwise from the current one.
+    fn marbles(&self) -> Box<dyn Iterator<Item = usize> + '_>;
+}
+
+fn out_of_memory(n: usize) -> StarError {
+    format!("Not enough memory for {} marbles", n).into()
+}
+
+/// The current marble is at the front, the back is counter-clockwise of it.
+impl Circle for VecDeque<usize> {
+    fn with_marble(marble: usize, n: usize) -> Result<Self, StarError> {
+        let mut circle = VecDeque::new();
+        circle
+            .try_reserve_exact(n.saturating_add(1))
+            .map_err(|_| out_of_memory(n))?;
+        circle.push_back(marble);
+        Ok(circle)
+    }
+    fn rotate_cw(&mut self, n: usize) {
+        for _ in 0..n {
+            let t = self.pop_front().unwrap();
+            self.push_back(t);
+        }
+    }
+    fn rotate_ccw(&mut self, n: usize) {
+        for _ in 0..n {
+            let t = self.pop_back().unwrap();
+            self.push_front(t);
+        }
+    }
+    fn insert(&mut self, marble: usize) {
+        self.push_front(marble);
+    }
+    fn remove(&mut self) -> usize {
+        self.pop_front().unwrap()
+    }
+    fn push(&mut self, marble: usize) {
+        self.push_back(marble);
+    }
+    fn marbles(&self) -> Box<dyn Iterator<Item = usize> + '_> {
+        Box::new(self.iter().cloned())
     }
 }
 
+/// The marble numbers stored by a `LinkedCircle`.
+trait Link: Copy + Eq + Default {
+    /// The number of marbles that fit.
+    const LIMIT: usize;
+    fn from_marble(m: usize) -> Self;
+    fn marble(self) -> usize;
+}
+
+impl Link for u32 {
+    const LIMIT: usize = u32::MAX as usize;
+    fn from_marble(m: usize) -> Self {
+        m as u32
+    }
+    fn marble(self) -> usize {
+        self as usize
+    }
+}
+
+impl Link for usize {
+    const LIMIT: usize = usize::MAX;
+    fn from_marble(m: usize) -> Self {
+        m
+    }
+    fn marble(self) -> usize {
+        self
+    }
+}
+
+/// Whether a game up to marble `n` needs wider links than `u32`, which take half the memory.
+fn wide_links(n: usize) -> bool {
+    n >= <u32 as Link>::LIMIT
+}
+
+/// A doubly linked list in a flat array indexed by marble, as every marble is used only once.
+/// Every entry holds the counter-clockwise and the clockwise neighbour.
 #[derive(Debug)]
-struct Playfield {
-    circle: VecDeque<usize>,
+struct LinkedCircle<L = u32> {
+    links: Vec<[L; 2]>,
+    current: L,
 }
 
-impl Playfield {
-    fn insert(&mut self, marble: usize) -> usize {
-        if marble % 23 != 0 {
-            self.rotate_cw(2);
-            self.circle.push_front(marble);
-            0
-        } else {
-            self.rotate_ccw(7);
-            marble + self.circle.pop_front().unwrap()
+impl<L: Link> Circle for LinkedCircle<L> {
+    fn with_marble(marble: usize, n: usize) -> Result<Self, StarError> {
+        if n >= L::LIMIT {
+            return Err(format!("Too many marbles for the links of a circle: {}", n).into());
         }
+        let mut links = Vec::new();
+        links
+            .try_reserve_exact(n + 1)
+            .map_err(|_| out_of_memory(n))?;
+        links.resize(n + 1, [L::default(); 2]);
+        links[marble] = [L::from_marble(marble); 2];
+        Ok(LinkedCircle {
+            links,
+            current: L::from_marble(marble),
+        })
     }
-    fn rotate_cw(&mut self, n: isize) {
+    fn rotate_cw(&mut self, n: usize) {
         for _ in 0..n {
-            let t = self.circle.pop_front().unwrap();
-            self.circle.push_back(t);
+            self.current = self.links[self.current.marble()][1];
         }
     }
-    fn rotate_ccw(&mut self, n: isize) {
+    fn rotate_ccw(&mut self, n: usize) {
         for _ in 0..n {
-            let t = self.circle.pop_back().unwrap();
-            self.circle.push_front(t);
+            self.current = self.links[self.current.marble()][0];
         }
     }
+    fn insert(&mut self, marble: usize) {
+        self.push(marble);
+        self.current = L::from_marble(marble);
+    }
+    fn remove(&mut self) -> usize {
+        let marble = self.current;
+        let [prev, next] = self.links[marble.marble()];
+        self.links[prev.marble()][1] = next;
+        self.links[next.marble()][0] = prev;
+        self.current = next;
+        marble.marble()
+    }
+    fn push(&mut self, marble: usize) {
+        let next = self.current;
+        let prev = self.links[next.marble()][0];
+        self.links[marble] = [prev, next];
+        let marble = L::from_marble(marble);
+        self.links[prev.marble()][1] = marble;
+        self.links[next.marble()][0] = marble;
+    }
+    fn marbles(&self) -> Box<dyn Iterator<Item = usize> + '_> {
+        let first = self.current;
+        let next = move |m: &L| Some(self.links[m.marble()][1]).filter(|&n| n != first);
+        Box::new(std::iter::successors(Some(first), next).map(L::marble))
+    }
 }
-impl Default for Playfield {
+
+/// The constants of the game: every `special`th marble is kept by the player, along with the
+/// marble `remove` places counter-clockwise of the current one. Other marbles are placed
+/// between the marbles `insert - 1` and `insert` places clockwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Rules {
+    special: usize,
+    remove: usize,
+    insert: usize,
+}
+
+impl Default for Rules {
     fn default() -> Self {
-        Playfield {
-            circle: VecDeque::from_iter(vec![0usize]),
+        Rules {
+            special: 23,
+            remove: 7,
+            insert: 2,
         }
     }
 }
 
-fn play(p: usize, n: usize) -> usize {
-    let mut playfield = Playfield::default();
-    playfield.circle.reserve(n + 1);
-    let mut scoreboard = Vec::new();
-    scoreboard.resize(p, 0);
-    for m in 1..=n {
-        let score = playfield.insert(m);
-        scoreboard[(m - 1) % p] += score;
+impl Rules {
+    /// Takes the rules from `--special=<n>`, `--remove=<n>` and `--insert=<n>`.
+    fn from_options(opts: &super::Options) -> Result<Rules, StarError> {
+        let default = Rules::default();
+        let rules = Rules {
+            special: opts.value("special", default.special)?,
+            remove: opts.value("remove", default.remove)?,
+            insert: opts.value("insert", default.insert)?,
+        };
+        // Keeps at least one marble in the circle.
+        if rules.special < 2 {
+            return Err("Special marbles have to be at least 2 apart".into());
+        }
+        Ok(rules)
     }
-    *scoreboard.iter().max().expect("Expected high score")
 }
 
-pub fn star1(lines: impl Iterator<Item = std::io::Result<String>>) -> StarResult {
-    for (p, n) in lines.map(parse_input).filter_map(|r| r.ok()) {
-        let highscore = play(p, n);
-        println!(
-            "{} players, last marble is worth {} points: high score is {}",
-            p, n, highscore
-        );
+#[derive(Debug)]
+struct Playfield<C> {
+    circle: C,
+}
+
+impl<C: Circle> Playfield<C> {
+    fn new(n: usize) -> Result<Self, StarError> {
+        Ok(Playfield { circle: C::new(n)? })
+    }
+    fn insert(&mut self, marble: usize, rules: &Rules) -> usize {
+        if !marble.is_multiple_of(rules.special) {
+            self.circle.rotate_cw(rules.insert);
+            self.circle.insert(marble);
+            0
+        } else {
+            self.circle.rotate_ccw(rules.remove);
+            marble + self.circle.remove()
+        }
+    }
+}
+
+/// A turn of the game, numbered like the marble placed in it, and the points the player scored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Turn {
+    turn: usize,
+    player: usize,
+    points: usize,
+}
+
+/// The turns recorded while playing: all of them, or only those in which the player scored.
+#[derive(Debug)]
+struct Log {
+    scoring_only: bool,
+    turns: Vec<Turn>,
+}
+
+impl Log {
+    fn new(scoring_only: bool) -> Log {
+        Log {
+            scoring_only,
+            turns: Vec::new(),
+        }
+    }
+
+    /// The log asked for with `--log`, which records every turn, or `--log=scoring`.
+    fn from_options(opts: &super::Options) -> Result<Option<Log>, StarError> {
+        match opts.get("log") {
+            None => Ok(None),
+            Some("") | Some("all") => Ok(Some(Log::new(false))),
+            Some("scoring") => Ok(Some(Log::new(true))),
+            Some(l) => Err(format!("Unknown log \"{}\"", l).into()),
+        }
+    }
+}
+
+/// The final score of every player, the first player with the highest score, and the logged
+/// turns if they were asked for.
+#[derive(Debug)]
+struct Results {
+    scores: Vec<usize>,
+    winner: usize,
+    log: Option<Vec<Turn>>,
+}
+
+impl Results {
+    fn high_score(&self) -> usize {
+        self.scores[self.winner]
+    }
+}
+
+/// A game in progress: the marble to be placed next, the scores so far and the circle.
+#[derive(Debug)]
+struct Game<C> {
+    rules: Rules,
+    last: usize,
+    next: usize,
+    scores: Vec<usize>,
+    playfield: Playfield<C>,
+}
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"MRBL";
+const SNAPSHOT_VERSION: u16 = 2;
+/// The size of the magic bytes, the version and the six numbers of the header.
+const SNAPSHOT_HEADER_LEN: u64 = 4 + 2 + 6 * 8;
+/// The number of marbles placed between looking at the time for `--every`.
+const CHECKPOINT_STEP: usize = 1 << 16;
+
+fn read_bytes(r: &mut impl Read, buf: &mut [u8]) -> Result<(), StarError> {
+    r.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => "Snapshot ends early".into(),
+        _ => e.into(),
+    })
+}
+
+fn read_u32(r: &mut impl Read) -> Result<usize, StarError> {
+    let mut b = [0; 4];
+    read_bytes(r, &mut b)?;
+    Ok(u32::from_le_bytes(b) as usize)
+}
+
+fn read_u64(r: &mut impl Read) -> Result<usize, StarError> {
+    let mut b = [0; 8];
+    read_bytes(r, &mut b)?;
+    Ok(u64::from_le_bytes(b) as usize)
+}
+
+/// The start of a snapshot, before the scores and the circle.
+#[derive(Debug)]
+struct Header {
+    rules: Rules,
+    last: usize,
+    next: usize,
+    players: usize,
+}
+
+impl Header {
+    /// The number of marbles in the circle: every one placed so far, except for the special
+    /// ones and those taken away with them.
+    fn circle_len(&self) -> usize {
+        self.next - 2 * ((self.next - 1) / self.rules.special)
+    }
+
+    /// The size of the whole snapshot, if it fits in 64 bits.
+    fn snapshot_len(&self) -> Option<u64> {
+        let width = if wide_links(self.last) { 8 } else { 4 };
+        let scores = (self.players as u64).checked_mul(8)?;
+        let circle = (self.circle_len() as u64).checked_mul(width)?;
+        SNAPSHOT_HEADER_LEN.checked_add(scores)?.checked_add(circle)
+    }
+
+    /// Reads the header of a snapshot of `size` bytes, checking that it holds as many scores and
+    /// marbles as the header says before anything is allocated for them.
+    fn read(r: &mut impl Read, size: u64) -> Result<Header, StarError> {
+        let mut magic = [0; 4];
+        read_bytes(r, &mut magic)?;
+        if magic != *SNAPSHOT_MAGIC {
+            return Err("Not a marble game snapshot".into());
+        }
+        let mut version = [0; 2];
+        read_bytes(r, &mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version != SNAPSHOT_VERSION {
+            return Err(format!("Unsupported snapshot version {}", version).into());
+        }
+        let rules = Rules {
+            special: read_u64(r)?,
+            remove: read_u64(r)?,
+            insert: read_u64(r)?,
+        };
+        let last = read_u64(r)?;
+        let next = read_u64(r)?;
+        let players = read_u64(r)?;
+        if rules.special < 2 || players == 0 || next == 0 || next > last.saturating_add(1) {
+            return Err("Inconsistent snapshot".into());
+        }
+        let header = Header {
+            rules,
+            last,
+            next,
+            players,
+        };
+        match header.snapshot_len() {
+            Some(len) if len == size => Ok(header),
+            Some(len) => Err(format!("Snapshot has {} bytes, expected {}", size, len).into()),
+            None => Err("Inconsistent snapshot".into()),
+        }
+    }
+}
+
+impl<C: Circle> Game<C> {
+    fn new(p: usize, n: usize, rules: Rules) -> Result<Self, StarError> {
+        Ok(Game {
+            rules,
+            last: n,
+            next: 1,
+            scores: vec![0; p],
+            playfield: Playfield::new(n)?,
+        })
     }
 
+    fn finished(&self) -> bool {
+        self.next > self.last
+    }
+
+    /// Places the marbles up to `until`, adding the turns to `log` if given.
+    fn play_until(&mut self, until: usize, log: &mut Option<Log>) {
+        let p = self.scores.len();
+        for m in self.next..=until.min(self.last) {
+            let score = self.playfield.insert(m, &self.rules);
+            let player = (m - 1) % p;
+            self.scores[player] += score;
+            if let Some(log) = log.as_mut().filter(|l| score > 0 || !l.scoring_only) {
+                log.turns.push(Turn {
+                    turn: m,
+                    player,
+                    points: score,
+                });
+            }
+        }
+        self.next = self.next.max(until.min(self.last) + 1);
+    }
+
+    fn results(self, log: Option<Log>) -> Results {
+        let winner = (0..self.scores.len())
+            .rev()
+            .max_by_key(|&i| self.scores[i])
+            .expect("Expected a player");
+        Results {
+            scores: self.scores,
+            winner,
+            log: log.map(|l| l.turns),
+        }
+    }
+
+    /// Writes the game as the magic bytes `MRBL`, a 16 bit version and 64 bit numbers: the
+    /// rules' special, remove and insert distances, the last marble, the next marble, the
+    /// number of players and their scores. Then follow the marbles clockwise from the current
+    /// one, as 32 bit numbers unless the last marble doesn't fit. Numbers are little endian.
+    fn save(&self, mut w: impl Write) -> io::Result<()> {
+        w.write_all(SNAPSHOT_MAGIC)?;
+        w.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        let header = [
+            self.rules.special,
+            self.rules.remove,
+            self.rules.insert,
+            self.last,
+            self.next,
+            self.scores.len(),
+        ];
+        for &n in header.iter().chain(&self.scores) {
+            w.write_all(&(n as u64).to_le_bytes())?;
+        }
+        let wide = wide_links(self.last);
+        for m in self.playfield.circle.marbles() {
+            if wide {
+                w.write_all(&(m as u64).to_le_bytes())?;
+            } else {
+                w.write_all(&(m as u32).to_le_bytes())?;
+            }
+        }
+        w.flush()
+    }
+
+    /// Continues a game from the rest of a snapshot after its header. The circle is set up for
+    /// marbles up to the last one, like for a new game, before anything else that grows with
+    /// the number of marbles.
+    fn resume(header: Header, mut r: impl Read) -> Result<Self, StarError> {
+        let len = header.circle_len();
+        let Header {
+            rules,
+            last,
+            next,
+            players,
+        } = header;
+        let scores = (0..players)
+            .map(|_| read_u64(&mut r))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let wide = wide_links(last);
+        let mut read_marble = || {
+            let m = if wide {
+                read_u64(&mut r)?
+            } else {
+                read_u32(&mut r)?
+            };
+            if m < next {
+                Ok(m)
+            } else {
+                Err(StarError::from(format!("Bad marble {} in snapshot", m)))
+            }
+        };
+        let first = read_marble()?;
+        let mut circle = C::with_marble(first, last)?;
+        let mut seen = vec![0u64; next / 64 + 1];
+        seen[first / 64] |= 1 << (first % 64);
+        for _ in 1..len {
+            let m = read_marble()?;
+            if seen[m / 64] & 1 << (m % 64) != 0 {
+                return Err(format!("Bad marble {} in snapshot", m).into());
+            }
+            seen[m / 64] |= 1 << (m % 64);
+            circle.push(m);
+        }
+        if r.read(&mut [0])? != 0 {
+            return Err("Snapshot has data after the circle".into());
+        }
+
+        Ok(Game {
+            rules,
+            last,
+            next,
+            scores,
+            playfield: Playfield { circle },
+        })
+    }
+}
+
+/// Plays a whole game in one go.
+#[cfg(test)]
+fn play<C: Circle>(p: usize, n: usize, rules: &Rules, mut log: Option<Log>) -> Results {
+    let mut game = Game::<C>::new(p, n, *rules).expect("Unable to set up the game");
+    game.play_until(n, &mut log);
+    game.results(log)
+}
+
+/// Plays a game to the end. With `--checkpoint=<file>` a snapshot is saved at most every
+/// `--every=<seconds>`, a minute by default, and once the game is over. Every snapshot writes
+/// the whole circle, 4 bytes per marble or 8 past `u32`, up to about 26 MB for the game of 7
+/// million marbles: short intervals spend more time saving than playing. With `--log`
+/// every turn is recorded, with `--log=scoring` only those in which the player scored.
+fn run<C: Circle>(mut game: Game<C>, opts: &super::Options) -> Result<Results, StarError> {
+    let mut log = Log::from_options(opts)?;
+    match opts.get("checkpoint") {
+        Some(file) => {
+            let every = Duration::from_secs(opts.value("every", 60)?);
+            let mut saved = Instant::now();
+            while !game.finished() {
+                let until = game.next.saturating_add(CHECKPOINT_STEP - 1);
+                game.play_until(until, &mut log);
+                if !game.finished() && saved.elapsed() < every {
+                    continue;
+                }
+                // Replaces the previous snapshot only once the new one is complete.
+                let tmp = format!("{}.tmp", file);
+                game.save(BufWriter::new(File::create(&tmp)?))?;
+                std::fs::rename(&tmp, file)?;
+                saved = Instant::now();
+            }
+        }
+        None => game.play_until(game.last, &mut log),
+    }
+    Ok(game.results(log))
+}
+
+/// Plays with the circle given by `--circle=list|deque`, the linked list by default, and the
+/// rules given by `Rules::from_options`.
+fn play_with(p: usize, n: usize, opts: &super::Options) -> Result<Results, StarError> {
+    let rules = Rules::from_options(opts)?;
+    match opts.get("circle") {
+        None | Some("list") if wide_links(n) => {
+            run(Game::<LinkedCircle<usize>>::new(p, n, rules)?, opts)
+        }
+        None | Some("list") => run(Game::<LinkedCircle>::new(p, n, rules)?, opts),
+        Some("deque") => run(Game::<VecDeque<usize>>::new(p, n, rules)?, opts),
+        Some(c) => Err(format!("Unknown circle \"{}\"", c).into()),
+    }
+}
+
+/// Continues the game saved in `--resume=<file>`, taking the same options as the stars except
+/// for `--log`, as snapshots don't hold the turns played before them.
+pub fn resume(opts: &super::Options) -> StarResult {
+    let file = opts
+        .get("resume")
+        .filter(|f| !f.is_empty())
+        .ok_or("No snapshot given")?;
+    if opts.flag("log") {
+        return Err("--log can't be used with --resume, snapshots don't hold the turns".into());
+    }
+    let file = File::open(file)?;
+    let size = file.metadata()?.len();
+    let mut r = BufReader::new(file);
+    let header = Header::read(&mut r, size)?;
+    let (p, n, results) = match opts.get("circle") {
+        None | Some("list") if wide_links(header.last) => {
+            let game = Game::<LinkedCircle<usize>>::resume(header, r)?;
+            (game.scores.len(), game.last, run(game, opts)?)
+        }
+        None | Some("list") => {
+            let game = Game::<LinkedCircle>::resume(header, r)?;
+            (game.scores.len(), game.last, run(game, opts)?)
+        }
+        Some("deque") => {
+            let game = Game::<VecDeque<usize>>::resume(header, r)?;
+            (game.scores.len(), game.last, run(game, opts)?)
+        }
+        Some(c) => return Err(format!("Unknown circle \"{}\"", c).into()),
+    };
+    print_results(p, n, &results);
     Ok(())
 }
 
-pub fn star2(lines: impl Iterator<Item = std::io::Result<String>>) -> StarResult {
-    for (p, n) in lines.map(parse_input).filter_map(|r| r.ok()) {
-        let highscore = play(p, 100 * n);
-        println!(
-            "{} players, last marble is worth {} points: high score is {}",
-            p,
-            100 * n,
-            highscore
-        );
+fn print_results(p: usize, n: usize, results: &Results) {
+    println!(
+        "{} players, last marble is worth {} points: high score is {} by player {}",
+        p,
+        n,
+        results.high_score(),
+        results.winner + 1
+    );
+    if let Some(log) = &results.log {
+        println!("turn,player,points");
+        for t in log {
+            println!("{},{},{}", t.turn, t.player + 1, t.points);
+        }
+    }
+}
+
+pub fn star1(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    opts: &super::Options,
+) -> StarResult {
+    for (p, n) in parse_input(lines)? {
+        print_results(p, n, &play_with(p, n, opts)?);
+    }
+
+    Ok(())
+}
+
+pub fn star2(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    opts: &super::Options,
+) -> StarResult {
+    for (p, n) in parse_input(lines)? {
+        print_results(p, 100 * n, &play_with(p, 100 * n, opts)?);
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn circles() {
+        for &(p, n, score) in &[(9, 25, 32), (10, 1618, 8317), (30, 5807, 37305)] {
+            let rules = Rules::default();
+            assert_eq!(
+                play::<VecDeque<usize>>(p, n, &rules, None).high_score(),
+                score
+            );
+            assert_eq!(play::<LinkedCircle>(p, n, &rules, None).high_score(), score);
+            assert_eq!(
+                play::<LinkedCircle<usize>>(p, n, &rules, None).high_score(),
+                score
+            );
+        }
+        assert!(!wide_links(7_206_100));
+        assert!(wide_links(5_000_000_000));
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_circles() {
+        let start = Instant::now();
+        let deque = play::<VecDeque<usize>>(428, 7_206_100, &Rules::default(), None);
+        let deque_time = start.elapsed();
+        let start = Instant::now();
+        let list = play::<LinkedCircle>(428, 7_206_100, &Rules::default(), None);
+        let list_time = start.elapsed();
+        println!("deque: {:?}, list: {:?}", deque_time, list_time);
+        assert_eq!(deque.scores, list.scores);
+    }
+
+    #[test]
+    fn example() {
+        let games = parse_input(solve("src/day09/test").unwrap()).unwrap();
+        let scores = games
+            .iter()
+            .map(|&(p, n)| play::<LinkedCircle>(p, n, &Rules::default(), None).high_score())
+            .collect::<Vec<_>>();
+        assert_eq!(scores, vec![32, 8317, 146373, 2764, 54718, 37305]);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_game("428 players; last marble is worth 72061 points").unwrap(),
+            (428, 72061)
+        );
+        assert_eq!(parse_game(" 428  72061 ").unwrap(), (428, 72061));
+
+        let lines = [
+            "9 25",
+            "",
+            "nine 25",
+            "10 players; last marble is worth 1618",
+            "0 25",
+        ];
+        match parse_input(lines.iter().map(|l| Ok(l.to_string()))) {
+            Err(StarError::StringError(e)) => {
+                let e = e.lines().collect::<Vec<_>>();
+                assert_eq!(e.len(), 3);
+                assert_eq!(
+                    e[0],
+                    "Line 3: Bad number of players \"nine\": invalid digit found in string"
+                );
+                assert!(e[1].starts_with("Line 4: Expected a game"), "{}", e[1]);
+                assert_eq!(e[2], "Line 5: A game needs at least one player");
+            }
+            r => panic!("Expected an error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn rules() {
+        let results = play::<LinkedCircle>(9, 25, &Rules::default(), Some(Log::new(false)));
+        assert_eq!(results.scores, vec![0, 0, 0, 0, 32, 0, 0, 0, 0]);
+        assert_eq!(results.winner, 4);
+        let log = results.log.unwrap();
+        assert_eq!(log.len(), 25);
+        assert_eq!(
+            log[0],
+            Turn {
+                turn: 1,
+                player: 0,
+                points: 0
+            }
+        );
+        let scoring = Turn {
+            turn: 23,
+            player: 4,
+            points: 32,
+        };
+        assert_eq!(log[22], scoring);
+        assert_eq!(
+            play::<LinkedCircle>(9, 25, &Rules::default(), Some(Log::new(true))).log,
+            Some(vec![scoring])
+        );
+        assert_eq!(
+            play::<LinkedCircle>(9, 25, &Rules::default(), None).log,
+            None
+        );
+
+        // Every 5th marble scores together with the one 2 counter-clockwise, others go right
+        // after the current one: 0 1 / 0 1 2 / 0 1 2 3 / 0 1 2 3 4 / 5 scores with 2.
+        let rules = Rules {
+            special: 5,
+            remove: 2,
+            insert: 1,
+        };
+        let results = play::<LinkedCircle>(2, 5, &rules, None);
+        assert_eq!(results.scores, vec![7, 0]);
+        let deque = play::<VecDeque<usize>>(7, 2000, &rules, Some(Log::new(true)));
+        let list = play::<LinkedCircle>(7, 2000, &rules, Some(Log::new(true)));
+        assert_eq!(deque.scores, list.scores);
+        assert_eq!(deque.log, list.log);
+        assert_eq!(list.log.unwrap().len(), 400);
+
+        let mut opts = Options::default();
+        opts.values.insert("special".to_string(), "1".to_string());
+        assert!(Rules::from_options(&opts).is_err());
+        opts.values.insert("log".to_string(), "scores".to_string());
+        assert!(Log::from_options(&opts).is_err());
+    }
+
+    fn save<C: Circle>(game: &Game<C>) -> Vec<u8> {
+        let mut data = Vec::new();
+        game.save(&mut data).unwrap();
+        data
+    }
+
+    fn load<C: Circle>(mut data: &[u8]) -> Result<Game<C>, StarError> {
+        let size = data.len() as u64;
+        let header = Header::read(&mut data, size)?;
+        Game::resume(header, data)
+    }
+
+    fn load_err(data: &[u8]) -> String {
+        load_err_with::<LinkedCircle>(data)
+    }
+
+    fn load_err_with<C: Circle>(data: &[u8]) -> String {
+        match load::<C>(data) {
+            Err(StarError::StringError(e)) => e,
+            r => panic!("Expected an error, got {:?}", r.map(|g| g.next)),
+        }
+    }
+
+    /// A snapshot of the default rules with 64 bit marbles.
+    fn wide_snapshot(last: u64, next: u64, scores: &[u64], marbles: &[u64]) -> Vec<u8> {
+        let mut data = b"MRBL\x02\x00".to_vec();
+        let header = [23, 7, 2, last, next, scores.len() as u64];
+        for n in header.iter().chain(scores).chain(marbles) {
+            data.extend_from_slice(&n.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn snapshots() {
+        let rules = Rules::default();
+        let whole = play::<LinkedCircle>(13, 7999, &rules, None);
+        assert_eq!(whole.high_score(), 146373);
+
+        for &stop in &[1, 22, 23, 4000, 7998, 7999] {
+            let mut game = Game::<LinkedCircle>::new(13, 7999, rules).unwrap();
+            game.play_until(stop, &mut None);
+            let data = save(&game);
+
+            let mut list = load::<LinkedCircle>(&data).unwrap();
+            assert_eq!(save(&list), data);
+            list.play_until(7999, &mut None);
+            assert_eq!(list.results(None).scores, whole.scores);
+
+            let mut deque = load::<VecDeque<usize>>(&data).unwrap();
+            assert_eq!(save(&deque), data);
+            deque.play_until(7999, &mut None);
+            assert_eq!(deque.results(None).scores, whole.scores);
+
+            let mut wide = load::<LinkedCircle<usize>>(&data).unwrap();
+            wide.play_until(7999, &mut None);
+            assert_eq!(wide.results(None).scores, whole.scores);
+        }
+
+        let mut game = Game::<VecDeque<usize>>::new(9, 25, rules).unwrap();
+        game.play_until(3, &mut None);
+        let data = save(&game);
+        assert_eq!(&data[..6], b"MRBL\x02\x00");
+        // The header, 9 scores and 4 marbles of 32 bits: 3 0 2 1 clockwise from marble 3.
+        assert_eq!(data.len(), 6 + 8 * (6 + 9) + 4 * 4);
+        let marbles = data[data.len() - 16..]
+            .chunks(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<_>>();
+        assert_eq!(marbles, vec![3, 0, 2, 1]);
+
+        assert_eq!(load_err(b"MRB"), "Snapshot ends early");
+        assert_eq!(load_err(b"MRBX\x02\x00"), "Not a marble game snapshot");
+        assert_eq!(load_err(b"MRBL\x01\x00"), "Unsupported snapshot version 1");
+        assert_eq!(load_err(&data[..30]), "Snapshot ends early");
+        assert_eq!(
+            load_err(&data[..data.len() - 1]),
+            "Snapshot has 141 bytes, expected 142"
+        );
+        let mut bad = data.clone();
+        bad.push(0);
+        assert_eq!(load_err(&bad), "Snapshot has 143 bytes, expected 142");
+        // The file may grow after its size is checked.
+        let header = Header::read(&mut &data[..], data.len() as u64).unwrap();
+        match Game::<LinkedCircle>::resume(header, &bad[54..]) {
+            Err(StarError::StringError(e)) => assert_eq!(e, "Snapshot has data after the circle"),
+            r => panic!("Expected an error, got {:?}", r.map(|g| g.next)),
+        }
+        let end = bad.len() - 1;
+        bad[end - 4..end].copy_from_slice(&3u32.to_le_bytes());
+        assert_eq!(load_err(&bad[..end]), "Bad marble 3 in snapshot");
+        bad[end - 4..end].copy_from_slice(&4u32.to_le_bytes());
+        assert_eq!(load_err(&bad[..end]), "Bad marble 4 in snapshot");
+
+        // Headers not matching the size of the snapshot are turned down before anything is
+        // allocated for them.
+        let mut bad = data.clone();
+        bad[30..38].copy_from_slice(&(1u64 << 50).to_le_bytes());
+        assert_eq!(load_err(&bad), "Snapshot has 142 bytes, expected 158");
+        bad[46..54].copy_from_slice(&(1u64 << 61).to_le_bytes());
+        assert_eq!(load_err(&bad), "Inconsistent snapshot");
+        bad[38..46].copy_from_slice(&0u64.to_le_bytes());
+        assert_eq!(load_err(&bad), "Inconsistent snapshot");
+
+        // More than 32 bit links can hold is an error, not a panic.
+        let wide = wide_snapshot(5_000_000_000, 4, &[0; 9], &[3, 0, 2, 1]);
+        assert!(load_err(&wide).starts_with("Too many marbles for the links of a circle"));
+        // A small snapshot of a huge game only allocates what a new game would: every other
+        // marble is special with these rules, so the circle stays small.
+        let mut huge = wide_snapshot(1 << 50, 1 << 50, &[0; 9], &[0, 1]);
+        huge[6..14].copy_from_slice(&2u64.to_le_bytes());
+        assert_eq!(
+            load_err_with::<VecDeque<usize>>(&huge),
+            "Not enough memory for 1125899906842624 marbles"
+        );
+    }
+
+    #[test]
+    fn checkpoints() {
+        let dir = std::env::temp_dir().join(format!("day09-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("game");
+        let file = file.to_str().unwrap();
+
+        let mut opts = Options::default();
+        opts.values
+            .insert("checkpoint".to_string(), file.to_string());
+        opts.values.insert("every".to_string(), "0".to_string());
+        let game = Game::<LinkedCircle>::new(10, 1618, Rules::default()).unwrap();
+        let results = run(game, &opts).unwrap();
+        assert_eq!(results.high_score(), 8317);
+
+        // The last checkpoint is the finished game.
+        let game = load::<LinkedCircle>(&std::fs::read(file).unwrap()).unwrap();
+        assert!(game.finished());
+        assert_eq!(game.results(None).scores, results.scores);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,9 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{self, Display};
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, Lines};
+use std::str::FromStr;
 use std::time::Instant;
 
 extern crate chrono;
@@ -58,7 +60,7 @@ impl From<String> for StarError {
         StarError::StringError(err)
     }
 }
-impl<'a> From<&'a str> for StarError {
+impl From<&str> for StarError {
     fn from(err: &str) -> StarError {
         StarError::StringError(err.to_string())
     }
@@ -71,11 +73,57 @@ fn map_error(r: io::Result<String>) -> Result<String, StarError> {
     r.map_err(|e| e.into())
 }
 
+/// Command line arguments: the day to run instead of today's, followed by `--name` flags and
+/// `--name=value` options for that day's solutions.
+#[derive(Debug, Default)]
+pub struct Options {
+    day: Option<u32>,
+    values: HashMap<String, String>,
+}
+
+impl Options {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Options, StarError> {
+        let mut opts = Options::default();
+        for arg in args {
+            if let Some(opt) = arg.strip_prefix("--") {
+                let mut kv = opt.splitn(2, '=');
+                let name = kv.next().unwrap_or_default().to_string();
+                let value = kv.next().unwrap_or_default().to_string();
+                opts.values.insert(name, value);
+            } else if opts.day.is_none() {
+                opts.day = Some(arg.parse()?);
+            } else {
+                return Err(format!("Unexpected argument \"{}\"", arg).into());
+            }
+        }
+        Ok(opts)
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    pub fn value<T: FromStr>(&self, name: &str, default: T) -> Result<T, StarError> {
+        match self.get(name) {
+            Some(v) => v
+                .parse()
+                .map_err(|_| format!("Invalid value for --{}: \"{}\"", name, v).into()),
+            None => Ok(default),
+        }
+    }
+}
+
 fn main() -> Result<(), StarError> {
+    let opts = Options::parse(std::env::args().skip(1))?;
     let local: DateTime<Local> = Local::now();
-    println!("Running day {}", local.day());
+    let day = opts.day.unwrap_or_else(|| local.day());
+    println!("Running day {}", day);
     let start = Instant::now();
-    match local.day() {
+    match day {
         1 => {
             day01::star1(solve("day01/input")?)?;
             day01::star2(solve("day01/input")?)?;
@@ -89,28 +137,52 @@ fn main() -> Result<(), StarError> {
             day03::star2(solve("day03/input")?)?;
         }
         4 => {
-            day04::star1(solve("day04/input")?)?;
-            day04::star2(solve("day04/input")?)?;
+            if opts.flag("report") {
+                day04::report(solve("day04/input")?, &opts)?;
+            } else if opts.flag("strategy") {
+                day04::select(solve("day04/input")?, &opts)?;
+            } else {
+                day04::star1(solve("day04/input")?, &opts)?;
+                day04::star2(solve("day04/input")?, &opts)?;
+            }
         }
         5 => {
-            day05::star1(solve("day05/input")?)?;
-            day05::star2(solve("day05/input")?)?;
+            day05::star1(solve("day05/input")?, &opts)?;
+            day05::star2(solve("day05/input")?, &opts)?;
         }
         6 => {
-            day06::star1(solve("day06/input")?)?;
-            day06::star2(solve("day06/input")?)?;
+            day06::star1(solve("day06/input")?, &opts)?;
+            day06::star2(solve("day06/input")?, &opts)?;
         }
         7 => {
-            day07::star1(solve("day07/input")?)?;
-            day07::star2(solve("day07/input")?)?;
+            if opts.flag("schedule") {
+                day07::schedule(solve("day07/input")?, &opts)?;
+            } else if opts.flag("dot") {
+                day07::dot(solve("day07/input")?, &opts)?;
+            } else if opts.flag("critical") {
+                day07::critical(solve("day07/input")?, &opts)?;
+            } else {
+                day07::star1(solve("day07/input")?, &opts)?;
+                day07::star2(solve("day07/input")?, &opts)?;
+            }
         }
         8 => {
-            day08::star1(solve("day08/input")?)?;
-            day08::star2(solve("day08/input")?)?;
+            if opts.flag("print") {
+                day08::print(solve("day08/input")?, &opts)?;
+            } else if opts.flag("query") {
+                day08::query(solve("day08/input")?, &opts)?;
+            } else {
+                day08::star1(BufReader::new(File::open("day08/input")?))?;
+                day08::star2(BufReader::new(File::open("day08/input")?))?;
+            }
         }
         9 => {
-            day09::star1(solve("day09/input")?)?;
-            day09::star2(solve("day09/input")?)?;
+            if opts.flag("resume") {
+                day09::resume(&opts)?;
+            } else {
+                day09::star1(solve("day09/input")?, &opts)?;
+                day09::star2(solve("day09/input")?, &opts)?;
+            }
         }
         10 => {
             day10::star12(solve("day10/input")?)?;